@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use fs_err as fs;
+use serde::Deserialize;
+use structopt::clap::ArgMatches;
+
+use crate::Options;
+
+/// The name of the config file that run-in-roblox looks for in the current
+/// directory when `--config` isn't given.
+pub const CONFIG_FILE_NAME: &str = "run-in-roblox.toml";
+
+/// Defaults for a project's runs, read from a `run-in-roblox.toml` file. Paths
+/// are relative to the directory containing the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    place: Option<PathBuf>,
+    script: Option<OneOrMany<PathBuf>>,
+    port: Option<u16>,
+    timeout: Option<u64>,
+    stay_alive: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// Fills in any options that weren't given on the command line from the
+/// config file named by `--config`, or from `run-in-roblox.toml` in the current
+/// directory if there is one.
+pub fn apply_config_file(options: &mut Options, matches: &ArgMatches) -> Result<(), anyhow::Error> {
+    let config_path = match &options.config {
+        Some(config_path) => config_path.clone(),
+        None => {
+            let default_path = PathBuf::from(CONFIG_FILE_NAME);
+
+            if !default_path.is_file() {
+                return Ok(());
+            }
+
+            default_path
+        }
+    };
+
+    let contents = fs::read_to_string(&config_path)?;
+    let config: Config = toml::from_str(&contents)
+        .with_context(|| format!("Could not parse config file {}", config_path.display()))?;
+
+    log::info!("Using config file {}", config_path.display());
+
+    let base_path = config_path.parent().unwrap_or_else(|| Path::new(""));
+
+    if options.place_path.is_none() && options.place_url.is_none() {
+        options.place_path = config.place.map(|place| base_path.join(place));
+    }
+
+    if options.script_paths.is_empty() {
+        if let Some(scripts) = config.script {
+            options.script_paths = scripts
+                .into_vec()
+                .into_iter()
+                .map(|script| base_path.join(script))
+                .collect();
+        }
+    }
+
+    // The port has a default value, so we need to ask clap whether it was
+    // actually given.
+    if matches.occurrences_of("port") == 0 {
+        if let Some(port) = config.port {
+            options.port = port;
+        }
+    }
+
+    if options.timeout.is_none() {
+        options.timeout = config.timeout;
+    }
+
+    if !options.stay_alive {
+        options.stay_alive = config.stay_alive.unwrap_or(false);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+/// The exit codes that run-in-roblox uses to say how a run went. Scripts that
+/// call `exit(code)` can exit with any code they like on top of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Every script ran without any errors.
+    Success = 0,
+
+    /// A script errored, logged an error, or had failing tests.
+    ScriptError = 1,
+
+    /// run-in-roblox itself failed, like when it couldn't find Studio.
+    HostError = 2,
+
+    /// Studio went quiet for longer than `--timeout`.
+    Timeout = 3,
+
+    /// Studio exited before the scripts finished.
+    StudioCrash = 4,
+
+    /// A script called `abort(reason)`.
+    Aborted = 5,
+
+    /// run-in-roblox was stopped with Ctrl-C, following the shell convention
+    /// of 128 plus the signal number.
+    Interrupted = 130,
+}
+
+impl ExitCode {
+    /// Every exit code, in order.
+    pub const ALL: [ExitCode; 7] = [
+        ExitCode::Success,
+        ExitCode::ScriptError,
+        ExitCode::HostError,
+        ExitCode::Timeout,
+        ExitCode::StudioCrash,
+        ExitCode::Aborted,
+        ExitCode::Interrupted,
+    ];
+
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ExitCode::Success => "The scripts ran without any errors",
+            ExitCode::ScriptError => "A script errored, logged an error, or had failing tests",
+            ExitCode::HostError => "run-in-roblox couldn't run the scripts",
+            ExitCode::Timeout => "Roblox Studio stopped responding for longer than --timeout",
+            ExitCode::StudioCrash => "Roblox Studio exited before the scripts finished",
+            ExitCode::Aborted => "A script aborted the run by calling abort()",
+            ExitCode::Interrupted => "run-in-roblox was interrupted with Ctrl-C",
+        }
+    }
+
+    /// Describes every exit code, for the end of `--help`.
+    pub fn help() -> String {
+        let mut help = String::from("EXIT CODES:\n");
+
+        for exit_code in &ExitCode::ALL {
+            help.push_str(&format!(
+                "    {:<8}{}\n",
+                exit_code.code(),
+                exit_code.description()
+            ));
+        }
+
+        help
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// The results that one script reported with `reportTestResults`.
+#[derive(Debug, Clone)]
+pub struct TestSuite {
+    pub name: String,
+    pub passed: u32,
+    pub failed: u32,
+    pub skipped: u32,
+
+    /// The names of the tests that failed.
+    pub failures: Vec<String>,
+}
+
+/// Writes a JUnit XML report, which most CI services can show natively.
+///
+/// Scripts only report the names of tests that failed, so tests that passed or
+/// were skipped are numbered instead. If no script reported any results, the
+/// whole run is reported as a single test that passes or fails along with it.
+/// That test is also added when the run failed for a reason that the reported
+/// tests don't show, like a script error or Studio crashing, so that a failed
+/// run never looks like a passing one.
+pub fn write_report<W: Write>(
+    mut output: W,
+    suites: &[TestSuite],
+    passed: bool,
+    errors: &[String],
+    duration: Duration,
+) -> io::Result<()> {
+    let reported_failures: u32 = suites.iter().map(failure_count).sum();
+    let include_run =
+        suites.is_empty() || (!passed && (reported_failures == 0 || !errors.is_empty()));
+
+    let run_tests = u32::from(include_run);
+    let run_failures = u32::from(include_run && !passed);
+
+    let total_tests: u32 = run_tests + suites.iter().map(test_count).sum::<u32>();
+    let total_failures = run_failures + reported_failures;
+
+    writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        output,
+        r#"<testsuites tests="{}" failures="{}" time="{:.3}">"#,
+        total_tests,
+        total_failures,
+        duration.as_secs_f64()
+    )?;
+
+    if include_run {
+        writeln!(
+            output,
+            r#"  <testsuite name="run-in-roblox" tests="1" failures="{}" skipped="0">"#,
+            run_failures
+        )?;
+
+        if passed {
+            writeln!(output, r#"    <testcase name="run"/>"#)?;
+        } else {
+            writeln!(output, r#"    <testcase name="run">"#)?;
+            writeln!(
+                output,
+                r#"      <failure message="The run failed">{}</failure>"#,
+                escape(&errors.join("\n"))
+            )?;
+            writeln!(output, "    </testcase>")?;
+        }
+
+        writeln!(output, "  </testsuite>")?;
+    }
+
+    for suite in suites {
+        writeln!(
+            output,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}">"#,
+            escape(&suite.name),
+            test_count(suite),
+            failure_count(suite),
+            suite.skipped
+        )?;
+
+        for failure in &suite.failures {
+            writeln!(output, r#"    <testcase name="{}">"#, escape(failure))?;
+            writeln!(output, r#"      <failure message="{}"/>"#, escape(failure))?;
+            writeln!(output, "    </testcase>")?;
+        }
+
+        // A script can report more failures than it names.
+        for index in suite.failures.len() as u32..suite.failed {
+            writeln!(output, r#"    <testcase name="Failed test {}">"#, index + 1)?;
+            writeln!(output, r#"      <failure message="Failed"/>"#)?;
+            writeln!(output, "    </testcase>")?;
+        }
+
+        for index in 0..suite.passed {
+            writeln!(
+                output,
+                r#"    <testcase name="Passed test {}"/>"#,
+                index + 1
+            )?;
+        }
+
+        for index in 0..suite.skipped {
+            writeln!(
+                output,
+                r#"    <testcase name="Skipped test {}">"#,
+                index + 1
+            )?;
+            writeln!(output, "      <skipped/>")?;
+            writeln!(output, "    </testcase>")?;
+        }
+
+        writeln!(output, "  </testsuite>")?;
+    }
+
+    writeln!(output, "</testsuites>")
+}
+
+/// The number of failing test cases written for a suite. A script can also
+/// name more failures than it counts, and every named failure gets a test case.
+fn failure_count(suite: &TestSuite) -> u32 {
+    (suite.failures.len() as u32).max(suite.failed)
+}
+
+/// The number of test cases written for a suite.
+fn test_count(suite: &TestSuite) -> u32 {
+    failure_count(suite) + suite.passed + suite.skipped
+}
+
+/// Escapes text for use in XML attributes and elements.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,2080 @@
+mod config;
+mod exit_code;
+mod junit;
+mod message_receiver;
+mod place;
+mod place_runner;
+mod plugin;
+mod project;
+mod studio;
+mod transcript;
+
+pub use crate::{
+    config::apply_config_file,
+    exit_code::ExitCode,
+    message_receiver::{
+        Message, MessageReceiver, MessageReceiverOptions, OutputLevel, RobloxMessage,
+        SERVER_ID_HEADER,
+    },
+    place_runner::{CancelToken, PlaceRunner, PlaceRunnerBuilder, RunTimings},
+    plugin::{wrap_script, ScriptContext, PROTOCOL_VERSION},
+    project::ProjectNode,
+};
+
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, BufReader, BufWriter, IsTerminal, LineWriter, Read, Seek, SeekFrom, Write},
+    net::IpAddr,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context};
+use colored::Colorize;
+use fs_err::{self as fs, create_dir_all};
+use indicatif::{ProgressBar, ProgressStyle};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use regex::Regex;
+use serde_json::json;
+use structopt::StructOpt;
+use tempfile::tempdir_in;
+
+use crate::{
+    junit::TestSuite,
+    studio::{close_studio, studio_is_running},
+    transcript::Transcript,
+};
+
+/// How long to wait for more changes after a script changes before running it
+/// again, since saving a file often shows up as several changes.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How often to check whether the run has been interrupted while waiting to
+/// hear from Studio.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    /// A path to the place file to open in Roblox Studio. If not specified, an
+    /// empty place file is used.
+    #[structopt(long("place"))]
+    pub place_path: Option<PathBuf>,
+
+    /// A URL to download the place file to open from. Ignored if `--place` is
+    /// also given.
+    #[structopt(long)]
+    pub place_url: Option<String>,
+
+    /// The format of the place file, either `rbxl` or `rbxlx`. If not
+    /// specified, the format is taken from the place's file extension, and
+    /// places without one are assumed to be `rbxl`.
+    #[structopt(long, possible_values = &["rbxl", "rbxlx"])]
+    pub place_format: Option<String>,
+
+    /// Convert XML places to the binary format before opening them, which
+    /// Roblox Studio loads much faster.
+    #[structopt(long)]
+    pub binary: bool,
+
+    /// A path to the script to run in Roblox Studio. Can be given more than
+    /// once to run several scripts in order in the same session.
+    ///
+    /// Scripts will be run at plugin-level security. Pass `-` to read the
+    /// script from stdin.
+    #[structopt(long("script"), number_of_values = 1)]
+    pub script_paths: Vec<PathBuf>,
+
+    /// Stop running scripts as soon as one of them fails. By default, the
+    /// remaining scripts are still run so that teardown scripts get a chance
+    /// to clean up.
+    #[structopt(long)]
+    pub fail_fast: bool,
+
+    /// Run the scripts this many times in a row in the same Studio session,
+    /// reporting counts for each run as well as in total. With `--fail-fast`,
+    /// stops after the first run that fails.
+    #[structopt(long, default_value = "1")]
+    pub repeat: u32,
+
+    /// Where the scripts run, one of `plugin`, `edit`, `run`, or `server`.
+    ///
+    /// `plugin` and `edit` run scripts inside the plugin while editing the
+    /// place. `run` starts running the game first, like pressing Run in
+    /// Studio. `server` also runs the game, and runs scripts as server Scripts
+    /// instead of inside the plugin.
+    #[structopt(
+        long,
+        default_value = "plugin",
+        possible_values = &["plugin", "edit", "run", "server"]
+    )]
+    pub context: ScriptContext,
+
+    /// A directory of scripts to make available to the script inside
+    /// ReplicatedStorage, so that it can require them. Files are turned into
+    /// instances using the same naming conventions as Rojo.
+    #[structopt(long)]
+    pub rojo_project: Option<PathBuf>,
+
+    /// An argument to pass to the script. Can be given more than once.
+    ///
+    /// Arguments are available to the script as varargs, e.g. `local first,
+    /// second = ...`.
+    #[structopt(long("arg"), number_of_values = 1)]
+    pub args: Vec<String>,
+
+    /// Determines whether the Roblox Studio instance will stay open after the
+    /// test concludes itself.
+    #[structopt(short("a"), long)]
+    pub stay_alive: bool,
+
+    /// Keep Roblox Studio open after the script finishes and keep showing
+    /// output from it until run-in-roblox is stopped with Ctrl-C. Studio is
+    /// left running afterwards.
+    #[structopt(long)]
+    pub open: bool,
+
+    /// Keep Roblox Studio open and run the scripts again each time one of
+    /// them is saved, until run-in-roblox is stopped with Ctrl-C.
+    #[structopt(long)]
+    pub watch: bool,
+
+    /// The address to listen for the plugin on. Listening on a different
+    /// address can help when Studio runs in its own network namespace, like in
+    /// some containers.
+    #[structopt(long("bind"), default_value = "127.0.0.1")]
+    pub bind_address: IpAddr,
+
+    /// The port that run-in-roblox should use to communicate with the plugin
+    /// running inside Roblox Studio.
+    #[structopt(long, default_value = "50312")]
+    pub port: u16,
+
+    /// Launch Roblox Studio through `xvfb-run`, which gives it a virtual
+    /// display on Linux machines that don't have one, like in CI.
+    #[structopt(long)]
+    pub xvfb: bool,
+
+    /// Launch Roblox Studio once and close it again before the real run. The
+    /// first launch in a wine prefix is much slower than the rest, so this
+    /// keeps that cost out of the run itself. Shows up as its own phase in
+    /// `--timings`.
+    #[structopt(long)]
+    pub warmup: bool,
+
+    /// A path to the Roblox Studio executable to launch, instead of the one
+    /// that run-in-roblox finds on its own. On Linux, the executable is started
+    /// through wine.
+    #[structopt(long)]
+    pub studio_path: Option<PathBuf>,
+
+    /// The name of Roblox Studio's executable, for finding copies of Studio
+    /// that are already running or that need to be closed. Can be given more
+    /// than once. Defaults to the names that Studio uses on this platform,
+    /// like `RobloxStudioBeta.exe` and `RobloxStudio.exe` on Windows.
+    #[structopt(long("studio-executable-name"), number_of_values = 1)]
+    pub studio_executable_names: Vec<String>,
+
+    /// The release channel of Roblox Studio to use on Windows, like `live` or
+    /// `zcanary`. The Roblox installer only records the channel that it last
+    /// updated Studio from, so the run fails unless the newest installed
+    /// version came from this channel. See `--list-studio` for the versions
+    /// that are installed and their channels.
+    #[structopt(long)]
+    pub studio_channel: Option<String>,
+
+    /// An extra argument to launch Roblox Studio with, added after the place
+    /// path. Can be given more than once.
+    #[structopt(long("studio-arg"), number_of_values = 1, allow_hyphen_values = true)]
+    pub studio_args: Vec<String>,
+
+    /// An environment variable to launch Roblox Studio with, given as
+    /// `KEY=VALUE`. Can be given more than once.
+    #[structopt(
+        long("env"),
+        number_of_values = 1,
+        parse(try_from_str = parse_env_var)
+    )]
+    pub studio_env: Vec<(String, String)>,
+
+    /// The wine prefix that Roblox Studio is installed in. Only used on
+    /// Linux, where it is passed to Studio as `WINEPREFIX`.
+    #[structopt(long)]
+    pub wine_prefix: Option<PathBuf>,
+
+    /// The directory to create run-in-roblox's temporary place folder in.
+    /// Defaults to the system temp directory, or `~/.tmp` on Linux so that the
+    /// place is reachable from wine.
+    #[structopt(long)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Don't delete the temporary folder holding the place that Studio opened,
+    /// so that it can be inspected after the run.
+    #[structopt(long)]
+    pub keep_temp: bool,
+
+    /// The number of seconds to wait without hearing from Roblox Studio before
+    /// giving up and closing it. Studio checks in regularly while a script is
+    /// running, so this only fires when Studio or the script has hung. This
+    /// only starts counting once the plugin has connected, which is covered by
+    /// --plugin-timeout instead. If not specified, run-in-roblox will wait
+    /// forever.
+    #[structopt(long)]
+    pub timeout: Option<u64>,
+
+    /// The number of seconds to wait for Roblox Studio to open the place and
+    /// start the plugin before giving up. Large places can take a while to
+    /// open, especially under wine.
+    #[structopt(long, default_value = "60")]
+    pub plugin_timeout: u64,
+
+    /// The number of times to try launching Roblox Studio again if it exits
+    /// right away or the plugin never connects, which happens now and then
+    /// under wine.
+    #[structopt(long, default_value = "0")]
+    pub launch_retries: u32,
+
+    /// A Lua file to use as the plugin instead of run-in-roblox's own, for
+    /// adding to what the plugin does. Placeholders like `{{PORT}}` and
+    /// `{{SERVER_ID}}` are filled in the same way as in the built-in plugin,
+    /// which is a good starting point.
+    #[structopt(long)]
+    pub plugin_path: Option<PathBuf>,
+
+    /// Remove any run-in-roblox plugins left in Roblox Studio's plugins folder
+    /// by earlier runs that didn't get to clean up after themselves.
+    #[structopt(long)]
+    pub clean_plugins: bool,
+
+    /// Have the plugin compress the output it sends to run-in-roblox, which
+    /// speeds up scripts that print a lot.
+    #[structopt(long)]
+    pub compress: bool,
+
+    /// How many messages from Roblox Studio can be waiting to be printed before
+    /// Studio is made to wait. Guards against running out of memory when a
+    /// script prints faster than run-in-roblox can keep up.
+    #[structopt(long, default_value = "1024")]
+    pub channel_capacity: usize,
+
+    /// The format to report output in, either `human`, `json`, or `junit`.
+    ///
+    /// In the `json` format, each message is printed as a JSON object on its
+    /// own line, followed by a final summary object.
+    ///
+    /// The `junit` format writes a JUnit XML report of the test results to
+    /// `--output-file` at the end of the run, and otherwise reports output
+    /// like `human`.
+    #[structopt(
+        long,
+        default_value = "human",
+        possible_values = &["human", "json", "junit"]
+    )]
+    pub format: OutputFormat,
+
+    /// Disables colored output. Setting the `NO_COLOR` environment variable
+    /// has the same effect.
+    #[structopt(long)]
+    pub no_color: bool,
+
+    /// A file to write all output to as it is received, along with when it
+    /// was received. Uses the same format as `--format`.
+    #[structopt(long)]
+    pub output_file: Option<PathBuf>,
+
+    /// A file to write a plain copy of the output shown in the terminal to,
+    /// without any colors. Unlike `--output-file`, this leaves the terminal's
+    /// output colored.
+    #[structopt(long)]
+    pub tee: Option<PathBuf>,
+
+    /// A folder to save files that scripts write with `writeFile` into.
+    #[structopt(long)]
+    pub artifact_dir: Option<PathBuf>,
+
+    /// A folder to copy the log files that Roblox Studio wrote during the run
+    /// into, which is useful for archiving them in CI.
+    #[structopt(long)]
+    pub studio_log_dir: Option<PathBuf>,
+
+    /// Prefix each line of output with the number of milliseconds since
+    /// run-in-roblox started.
+    #[structopt(long)]
+    pub timestamps: bool,
+
+    /// Report how long each part of the run took, like launching Studio and
+    /// running the script.
+    #[structopt(long)]
+    pub timings: bool,
+
+    /// Fail the run if the scripts don't print, warn, or error at all and
+    /// don't call `exit()`, which usually means that they never ran.
+    #[structopt(long)]
+    pub fail_on_no_output: bool,
+
+    /// Treat warnings as errors, failing the run if any warnings are emitted.
+    #[structopt(long)]
+    pub warnings_as_errors: bool,
+
+    /// The least severe level of output to show, one of `print`, `info`,
+    /// `warning`, or `error`. Output below this level is still counted in the
+    /// summary.
+    #[structopt(
+        long,
+        default_value = "print",
+        possible_values = &["print", "info", "warning", "error"]
+    )]
+    pub min_level: OutputLevel,
+
+    /// Only show output that matches this regular expression. Other output is
+    /// still counted.
+    #[structopt(long)]
+    pub grep: Option<Regex>,
+
+    /// Stop showing output after this many lines in each run, so that a
+    /// runaway script can't flood the terminal. Output is still counted.
+    #[structopt(long)]
+    pub max_output_lines: Option<u32>,
+
+    /// Hold output back until the run is over, then show only the last this
+    /// many lines of it if the run passed. If the run fails, all of its output
+    /// is shown, including the lines that were held back.
+    #[structopt(long)]
+    pub tail: Option<usize>,
+
+    /// Don't show any output from Studio, only test results and the summary
+    /// at the end. Output is still counted and written to `--output-file`.
+    #[structopt(short, long)]
+    pub quiet: bool,
+
+    /// Print only the number of errors, warnings, and prints, separated by
+    /// spaces, for easy parsing in shell scripts.
+    #[structopt(long)]
+    pub count_only: bool,
+
+    /// Exit with the number of failures as the exit code rather than 1. Errors,
+    /// failed tests, and files that couldn't be saved each count as a failure.
+    /// The count is capped at 125 so that it doesn't collide with exit codes
+    /// that shells reserve for themselves.
+    #[structopt(long)]
+    pub exit_code_count: bool,
+
+    /// Launch Roblox Studio even if it's already running.
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Install the run-in-roblox plugin into Roblox Studio's plugins folder
+    /// and exit without launching Studio. Useful for checking that
+    /// run-in-roblox can find Studio.
+    #[structopt(long)]
+    pub install_plugin_only: bool,
+
+    /// List every copy of Roblox Studio that run-in-roblox can find, newest
+    /// first, and exit. Useful for checking which version is being used.
+    #[structopt(long)]
+    pub list_studio: bool,
+
+    /// Print what run-in-roblox would do, like which place and port it would
+    /// use and the plugin it would install, without launching Studio. Nothing
+    /// is created or downloaded, and the scripts and the Rojo project are only
+    /// checked for, not read.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Print the source of the plugin that would be injected into Studio, with
+    /// this run's server ID and port filled in, and exit.
+    #[structopt(long)]
+    pub print_plugin: bool,
+
+    /// A config file to read defaults from, which command line options take
+    /// precedence over. Defaults to `run-in-roblox.toml` in the current
+    /// directory, if there is one.
+    #[structopt(long)]
+    pub config: Option<PathBuf>,
+
+    /// How to stop Roblox Studio when the run is over or has timed out, either
+    /// `term` to ask it to exit or `kill` to stop it right away.
+    #[structopt(
+        long("studio-timeout-kill-signal"),
+        default_value = "term",
+        possible_values = &["term", "kill"]
+    )]
+    pub kill_signal: KillSignal,
+
+    /// Log more about what run-in-roblox is doing. Can be given more than once
+    /// for more detail; `-vv` also shows Roblox Studio's own output.
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+}
+
+/// How to stop Roblox Studio when cleaning up after a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillSignal {
+    /// Ask Studio to exit, giving it a chance to flush its logs. This is
+    /// SIGTERM on Unix-like systems, and closing Studio's window on Windows.
+    Term,
+
+    /// Stop Studio right away. Some wine setups ignore SIGTERM.
+    Kill,
+}
+
+impl FromStr for KillSignal {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "term" => Ok(KillSignal::Term),
+            "kill" => Ok(KillSignal::Kill),
+            _ => Err(format!("Unknown kill signal '{}'", source)),
+        }
+    }
+}
+
+/// How output from Studio is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Junit,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            "junit" => Ok(OutputFormat::Junit),
+            _ => Err(format!("Unknown output format '{}'", source)),
+        }
+    }
+}
+
+/// The outcome of a run.
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    pub errors: u32,
+    pub warnings: u32,
+    pub infos: u32,
+    pub prints: u32,
+
+    /// The exit code that run-in-roblox should exit with.
+    pub exit_code: i32,
+
+    /// The counts from each time the scripts were run, in order.
+    pub iterations: Vec<OutputCounts>,
+
+    /// How long each part of the run took, in the order they happened.
+    pub timings: Vec<(&'static str, Duration)>,
+
+    /// The longest stretch of time that Studio didn't send us anything other
+    /// than heartbeats, which is usually where a script stalled.
+    pub longest_gap: Option<OutputGap>,
+}
+
+/// A stretch of time between two messages from Studio.
+#[derive(Debug, Clone)]
+pub struct OutputGap {
+    pub duration: Duration,
+
+    /// How long after run-in-roblox started that the gap began.
+    pub started_at: Duration,
+
+    /// The last output before the gap, if there was any.
+    pub after: Option<String>,
+}
+
+/// How many messages of each level were output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputCounts {
+    pub errors: u32,
+    pub warnings: u32,
+    pub infos: u32,
+    pub prints: u32,
+}
+
+impl OutputCounts {
+    /// Counts a message of the given level.
+    ///
+    /// Info output gets its own count instead of being counted as a print,
+    /// since it mostly comes from Studio itself rather than from scripts.
+    pub fn count(&mut self, level: OutputLevel) {
+        match level {
+            OutputLevel::Print => self.prints += 1,
+            OutputLevel::Info => self.infos += 1,
+            OutputLevel::Warning => self.warnings += 1,
+            OutputLevel::Error => self.errors += 1,
+        }
+    }
+
+    /// The counts of output that happened after `earlier` was taken.
+    pub fn since(&self, earlier: &OutputCounts) -> OutputCounts {
+        OutputCounts {
+            errors: self.errors - earlier.errors,
+            warnings: self.warnings - earlier.warnings,
+            infos: self.infos - earlier.infos,
+            prints: self.prints - earlier.prints,
+        }
+    }
+}
+
+/// Runs scripts in Roblox Studio according to the given options, showing
+/// their output as it arrives.
+pub fn run(options: Options) -> Result<RunSummary, anyhow::Error> {
+    run_with_cancel_token(options, CancelToken::new())
+}
+
+/// Like `run`, but stops early when `cancel_token` is cancelled, which is how
+/// Ctrl-C is handled. Studio is closed unless `--stay-alive` or `--open` was
+/// given, and the run ends with the `Interrupted` exit code, unless `--open`
+/// or `--watch` was given and the scripts have run, in which case it ends with
+/// the outcome of the last run.
+pub fn run_with_cancel_token(
+    mut options: Options,
+    cancel_token: CancelToken,
+) -> Result<RunSummary, anyhow::Error> {
+    // Only the counts are printed with --count-only, so that they're trivial
+    // to parse.
+    if options.count_only {
+        options.quiet = true;
+        options.no_color = true;
+    }
+
+    // JUnit reports go to --output-file once the run is over, and in the
+    // meantime output is shown as usual.
+    let junit_path = if options.format == OutputFormat::Junit {
+        options.format = OutputFormat::Human;
+
+        match options.output_file.take() {
+            Some(output_file) => Some(output_file),
+            None => bail!("--format junit needs --output-file to write the report to"),
+        }
+    } else {
+        None
+    };
+
+    let start_time = Instant::now();
+    let run_started = SystemTime::now();
+
+    validate_port(options.port)?;
+
+    // The server ID is the only thing keeping other programs from feeding us
+    // output, and it's sent in the clear.
+    if !options.bind_address.is_loopback() {
+        log::warn!(
+            "Listening on {}, which isn't a loopback address. Anything that can reach it \
+             will be able to talk to run-in-roblox.",
+            options.bind_address
+        );
+    }
+
+    if options.studio_channel.is_some() && !cfg!(windows) {
+        log::warn!("--studio-channel only has an effect on Windows");
+    }
+
+    if options.list_studio {
+        list_studio_installs(options.wine_prefix.as_deref());
+        return Ok(RunSummary::default());
+    }
+
+    if options.script_paths.is_empty() && !options.install_plugin_only {
+        bail!("No scripts to run. Pass one with --script, or set `script` in a config file.");
+    }
+
+    // Reading a directory fails with a confusing error from the OS, and it's
+    // an easy mistake to make when tab-completing paths.
+    for script_path in &options.script_paths {
+        if script_path.is_dir() {
+            bail!(
+                "Expected a script file but got a directory: {}",
+                script_path.display()
+            );
+        }
+    }
+
+    if let Some(place_path) = &options.place_path {
+        if place_path.is_dir() {
+            bail!(
+                "Expected a place file but got a directory: {}",
+                place_path.display()
+            );
+        }
+    }
+
+    if options.no_color || env::var_os("NO_COLOR").is_some() {
+        colored::control::set_override(false);
+    }
+
+    let server_id = place_runner::generate_server_id();
+
+    let plugin_template = match &options.plugin_path {
+        Some(plugin_path) => {
+            let plugin_template = fs::read_to_string(plugin_path)?;
+            plugin::check_template(&plugin_template)
+                .with_context(|| format!("Could not use plugin {}", plugin_path.display()))?;
+
+            Some(plugin_template)
+        }
+        None => None,
+    };
+
+    // The place path and script updates are filled in once the temp place
+    // and the watcher exist, so that --dry-run and --print-plugin don't
+    // create them. The same goes for the scripts and the project, which
+    // --script - would otherwise have to wait on stdin for. Until then, all
+    // that the plugin needs is how many scripts there are.
+    let mut place_runner = PlaceRunner {
+        bind_address: options.bind_address,
+        port: options.port,
+        place_path: PathBuf::new(),
+        server_id: server_id.clone(),
+        lua_scripts: vec![String::new(); options.script_paths.len()],
+        args: options.args.clone(),
+        fail_fast: options.fail_fast,
+        repeat: options.repeat,
+        studio_path: options.studio_path.clone(),
+        studio_channel: options.studio_channel.clone(),
+        studio_args: options.studio_args.clone(),
+        studio_env: options.studio_env.clone(),
+        wine_prefix: options.wine_prefix.clone(),
+        keep_open: options.open,
+        context: options.context,
+        connect_timeout: Duration::from_secs(options.plugin_timeout),
+        launch_retries: options.launch_retries,
+        clean_plugins: options.clean_plugins,
+        compress: options.compress,
+        channel_capacity: options.channel_capacity,
+        plugin_template,
+        xvfb: options.xvfb,
+        warmup: options.warmup,
+        kill_signal: options.kill_signal,
+        studio_executable_names: options.studio_executable_names.clone(),
+        cancel_token: CancelToken::new(),
+        script_updates: None,
+        project: None,
+    };
+
+    if options.print_plugin {
+        print!("{}", place_runner.render_plugin_source());
+
+        return Ok(RunSummary::default());
+    }
+
+    if options.dry_run {
+        // Nothing is created or downloaded, so the temp folder can't be named
+        // yet.
+        let (place_description, extension) = match (&options.place_path, &options.place_url) {
+            (Some(place_path), _) => (
+                place_path.display().to_string(),
+                place_extension(place_path, options.place_format.as_deref())?,
+            ),
+            (None, Some(place_url)) => (place_url.clone(), place::url_extension(place_url)?),
+            (None, None) => ("(empty place)".to_owned(), "rbxl"),
+        };
+
+        let extension = if options.binary { "rbxl" } else { extension };
+        let temp_folder_path =
+            temp_folder_parent(options.temp_dir.as_deref())?.join("<new folder>");
+        place_runner.place_path = temp_folder_path.join(temp_place_name(&server_id, extension));
+
+        let studio_command = place_runner
+            .describe_studio_command()
+            .unwrap_or_else(|err| format!("(not found: {})", err));
+
+        // Scripts and the project are only checked for, not read, so that a
+        // dry run doesn't wait on stdin for --script -.
+        for script_path in &options.script_paths {
+            if script_path != Path::new("-") {
+                fs::metadata(script_path)?;
+            }
+        }
+
+        if let Some(project_path) = &options.rojo_project {
+            fs::metadata(project_path)?;
+        }
+
+        println!("Place:          {}", place_description);
+        println!("Temp folder:    {}", temp_folder_path.display());
+        println!("Temp place:     {}", place_runner.place_path.display());
+        println!("Port:           {}", options.port);
+        println!("Server ID:      {}", server_id);
+        println!("Studio command: {}", studio_command);
+
+        if let Some(project_path) = &options.rojo_project {
+            println!("Project:        {}", project_path.display());
+        }
+
+        println!();
+        println!("{}", place_runner.render_plugin()?);
+
+        return Ok(RunSummary::default());
+    }
+
+    place_runner.lua_scripts = options
+        .script_paths
+        .iter()
+        .map(|script_path| read_script(script_path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    place_runner.project =
+        match &options.rojo_project {
+            Some(project_path) => Some(ProjectNode::read(project_path).with_context(|| {
+                format!("Could not read project from {}", project_path.display())
+            })?),
+            None => None,
+        };
+
+    let temp_setup_start = Instant::now();
+
+    // Create a temp directory to house our place, even if a path is given from
+    // the command line. This helps ensure Studio won't hang trying to tell the
+    // user that the place is read-only because of a .lock file.
+    let temp_dir_path = temp_folder_parent(options.temp_dir.as_deref())?;
+    create_dir_all(&temp_dir_path).with_context(|| {
+        format!(
+            "Could not create temp folder {}. Choose another with --temp-dir.",
+            temp_dir_path.display()
+        )
+    })?;
+    let temp_place_folder = tempdir_in(&temp_dir_path)?;
+
+    // With --keep-temp, we give up ownership of the folder so that it's never
+    // deleted, however the run ends.
+    let (temp_folder_path, _temp_place_folder) = if options.keep_temp {
+        let temp_folder_path = temp_place_folder.into_path();
+        eprintln!(
+            "Keeping temporary place folder at {}",
+            temp_folder_path.display()
+        );
+
+        (temp_folder_path, None)
+    } else {
+        (temp_place_folder.path().to_owned(), Some(temp_place_folder))
+    };
+    let temp_place_path;
+
+    let place_path = match (&options.place_path, &options.place_url) {
+        (Some(place_path), _) => Some(place_path.clone()),
+        (None, Some(place_url)) => Some(place::download_place(place_url, &temp_folder_path)?),
+        (None, None) => None,
+    };
+
+    match &place_path {
+        Some(place_path) => {
+            let extension = place_extension(place_path, options.place_format.as_deref())?;
+
+            if options.binary && extension == "rbxlx" {
+                temp_place_path = temp_folder_path.join(temp_place_name(&server_id, "rbxl"));
+
+                let input = BufReader::new(fs::File::open(place_path)?);
+                let output = BufWriter::new(fs::File::create(&temp_place_path)?);
+                place::convert_xml_to_binary(input, output)?;
+            } else {
+                temp_place_path = temp_folder_path.join(temp_place_name(&server_id, extension));
+
+                // The temp folder is often on a different filesystem than
+                // the place, so this has to be a copy rather than a rename.
+                fs::copy(place_path, &temp_place_path).with_context(|| {
+                    format!(
+                        "Could not copy the place from {} to {}",
+                        place_path.display(),
+                        temp_place_path.display()
+                    )
+                })?;
+                make_writable(&temp_place_path)?;
+            }
+        }
+        None => {
+            temp_place_path = temp_folder_path.join(temp_place_name(&server_id, "rbxl"));
+
+            let place_file = fs::File::create(&temp_place_path)?;
+            place::write_empty_place(place_file)?;
+        }
+    }
+
+    let temp_setup = temp_setup_start.elapsed();
+
+    place_runner.place_path = temp_place_path;
+
+    if options.watch {
+        place_runner.script_updates = Some(watch_scripts(&options.script_paths)?);
+    }
+
+    if options.install_plugin_only {
+        let plugin_file_path = place_runner.install_plugin_only()?;
+        println!("Installed plugin to {}", plugin_file_path.display());
+
+        return Ok(RunSummary::default());
+    }
+
+    let studio_session = place_runner.studio_session(options.kill_signal);
+
+    if !options.force && studio_is_running(&studio_session) {
+        bail!(
+            "Roblox Studio is already running, and the plugin might connect to it instead of the \
+             copy that run-in-roblox opens. Close Studio first, or pass --force to run anyway."
+        );
+    }
+
+    let mut transcript = match &options.output_file {
+        Some(output_file) => Some(Transcript::create(output_file, options.format)?),
+        None => None,
+    };
+
+    let mut tee = match &options.tee {
+        Some(tee_path) => Some(LineWriter::new(fs::File::create(tee_path)?)),
+        None => None,
+    };
+
+    let (sender, receiver) = mpsc::sync_channel(options.channel_capacity);
+
+    // This is separate from `cancel_token` because being interrupted doesn't
+    // always mean closing Studio, which cancelling the place runner does.
+    let runner_token = place_runner.cancel_token();
+    let place_runner_thread = thread::spawn(move || place_runner.run(sender));
+
+    let mut exit_code = ExitCode::Success.code();
+    let mut requested_exit_code = None;
+
+    // Failures that aren't errors in the output, for --exit-code-count.
+    let mut failed_tests = 0;
+    let mut failed_file_writes = 0;
+
+    let mut counts = OutputCounts::default();
+
+    // The counts from before each iteration started, which are turned into
+    // per-iteration counts when printing the summary.
+    let mut iteration_starts = Vec::new();
+
+    // Progress is shown as a bar when we're writing to a terminal, and as
+    // occasional lines otherwise.
+    let show_progress_bar = options.format == OutputFormat::Human && io::stderr().is_terminal();
+    let mut progress_bar: Option<ProgressBar> = None;
+    let mut last_progress_percent = None;
+
+    let mut timed_out = false;
+    let mut aborted = false;
+    let mut interrupted = false;
+    let mut studio_exited = false;
+
+    // What goes into the JUnit report, if we're writing one.
+    let mut test_suites = Vec::new();
+    let mut junit_errors = Vec::new();
+    let mut current_script: Option<usize> = None;
+
+    // How much output has been shown in this run, for --max-output-lines.
+    let mut printed_lines = 0;
+    let mut output_truncated = false;
+
+    // Output that --tail is holding back until we know whether the run passed.
+    let mut held_output = options.tail.map(TailBuffer::new);
+
+    let mut last_message_at = None;
+    let mut last_output = None;
+    let mut longest_gap = None;
+
+    // Nothing reaches us until the plugin has connected, and the place runner
+    // has its own timeout for that, which can be much longer than --timeout
+    // when Studio is slow to open the place.
+    let mut heard_from_studio = false;
+
+    'messages: loop {
+        // We wait in short slices so that being interrupted is noticed even
+        // while Studio is quiet. If the place runner fails, it will drop its
+        // end of the channel and we'll pick up its error when joining the
+        // thread below.
+        let waiting_since = Instant::now();
+        let message = loop {
+            if cancel_token.is_cancelled() {
+                interrupted = true;
+                break 'messages;
+            }
+
+            let mut wait = INTERRUPT_POLL_INTERVAL;
+
+            if let Some(timeout) = options.timeout.filter(|_| heard_from_studio) {
+                let timeout = Duration::from_secs(timeout);
+                let waited = waiting_since.elapsed();
+
+                if waited >= timeout {
+                    timed_out = true;
+                    break 'messages;
+                }
+
+                wait = wait.min(timeout - waited);
+            }
+
+            match receiver.recv_timeout(wait) {
+                Ok(message) => break message,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break 'messages,
+            }
+        };
+
+        let message = match message {
+            Some(message) => message,
+            None => break,
+        };
+
+        heard_from_studio = true;
+
+        // Heartbeats arrive every second no matter what the script is doing,
+        // so they'd hide any gaps.
+        if !matches!(message, RobloxMessage::Heartbeat) {
+            let now = start_time.elapsed();
+
+            if let Some(last_message_at) = last_message_at {
+                let gap = now - last_message_at;
+
+                if longest_gap
+                    .as_ref()
+                    .map_or(true, |longest: &OutputGap| gap > longest.duration)
+                {
+                    longest_gap = Some(OutputGap {
+                        duration: gap,
+                        started_at: last_message_at,
+                        after: last_output.clone(),
+                    });
+                }
+            }
+
+            last_message_at = Some(now);
+        }
+
+        if let RobloxMessage::Output { body, .. } = &message {
+            last_output = Some(body.clone());
+        }
+
+        match message {
+            RobloxMessage::Output {
+                level,
+                body,
+                source,
+                line,
+                studio_time,
+                context,
+            } => {
+                // Studio only knows our copies of the scripts, whose names
+                // mean nothing to the user.
+                let source = source.map(|source| script_source(source, &options.script_paths));
+
+                if let Some(transcript) = &mut transcript {
+                    transcript.write_output(level, &body, source.as_deref(), line)?;
+                }
+
+                if junit_path.is_some() && level == OutputLevel::Error {
+                    junit_errors.push(body.clone());
+                }
+
+                let show_output = should_show_output(
+                    &options,
+                    level,
+                    &body,
+                    &mut printed_lines,
+                    &mut output_truncated,
+                );
+
+                if show_output {
+                    let elapsed_ms = start_time.elapsed().as_millis();
+
+                    // Showing when Studio logged the output next to when we
+                    // received it tells delays in Studio and in getting the
+                    // output to us apart.
+                    let studio_ms = studio_time.map(|studio_time| (studio_time * 1000.0) as u64);
+                    let timestamp = match studio_ms {
+                        Some(studio_ms) => format!("[+{}ms, studio +{}ms]", elapsed_ms, studio_ms),
+                        None => format!("[+{}ms]", elapsed_ms),
+                    };
+
+                    match options.format {
+                        OutputFormat::Human | OutputFormat::Junit => {
+                            let body = match (&source, line) {
+                                (Some(source), Some(line)) => {
+                                    format!("[{}:{}] {}", source, line, body)
+                                }
+                                _ => body,
+                            };
+
+                            // Output from edit mode is all that most runs
+                            // have, so it isn't worth marking.
+                            let body = match context.as_deref() {
+                                Some(context) if context != "edit" => {
+                                    format!("[{}] {}", context, body)
+                                }
+                                _ => body,
+                            };
+
+                            if let Some(tee) = &mut tee {
+                                if options.timestamps {
+                                    writeln!(tee, "{} {}", timestamp, body)?;
+                                } else {
+                                    writeln!(tee, "{}", body)?;
+                                }
+                            }
+
+                            let colored_body = match level.color() {
+                                Some(color) => body.color(color),
+                                None => body.normal(),
+                            };
+
+                            let output = if options.timestamps {
+                                format!("{} {}", timestamp.dimmed(), colored_body)
+                            } else {
+                                colored_body.to_string()
+                            };
+
+                            print_output(output, &mut held_output, progress_bar.as_ref())?;
+                        }
+                        OutputFormat::Json => {
+                            let mut output = json!({
+                                "level": level.as_str(),
+                                "body": body,
+                                "source": source,
+                                "line": line,
+                                "context": context.as_deref().unwrap_or("edit"),
+                            });
+
+                            if options.timestamps {
+                                output["elapsed_ms"] = json!(elapsed_ms as u64);
+                                output["studio_ms"] = json!(studio_ms);
+                            }
+
+                            if let Some(tee) = &mut tee {
+                                writeln!(tee, "{}", output)?;
+                            }
+
+                            print_output(output.to_string(), &mut held_output, None)?;
+                        }
+                    }
+                }
+
+                counts.count(level);
+
+                if level == OutputLevel::Error
+                    || (level == OutputLevel::Warning && options.warnings_as_errors)
+                {
+                    exit_code = ExitCode::ScriptError.code();
+                }
+            }
+            RobloxMessage::ScriptError { message, traceback } => {
+                if junit_path.is_some() {
+                    junit_errors.push(format!("{}\n{}", message, traceback));
+                }
+
+                if let Some(transcript) = &mut transcript {
+                    let body = format!("{}\n{}", message, traceback);
+                    transcript.write_output(OutputLevel::Error, &body, None, None)?;
+                }
+
+                let show_output = should_show_output(
+                    &options,
+                    OutputLevel::Error,
+                    &message,
+                    &mut printed_lines,
+                    &mut output_truncated,
+                );
+
+                if show_output {
+                    match options.format {
+                        OutputFormat::Human | OutputFormat::Junit => {
+                            let output = format!("{}\n{}", message, traceback.trim_end());
+
+                            if let Some(tee) = &mut tee {
+                                writeln!(tee, "{}", output)?;
+                            }
+
+                            print_output(
+                                output.red().to_string(),
+                                &mut held_output,
+                                progress_bar.as_ref(),
+                            )?;
+                        }
+                        OutputFormat::Json => {
+                            let output = json!({
+                                "script_error": {
+                                    "message": message,
+                                    "traceback": traceback,
+                                }
+                            });
+
+                            if let Some(tee) = &mut tee {
+                                writeln!(tee, "{}", output)?;
+                            }
+
+                            print_output(output.to_string(), &mut held_output, None)?;
+                        }
+                    }
+                }
+
+                counts.count(OutputLevel::Error);
+                exit_code = ExitCode::ScriptError.code();
+            }
+            RobloxMessage::TestResults {
+                passed,
+                failed,
+                skipped,
+                failures,
+            } => {
+                if failed > 0 {
+                    exit_code = ExitCode::ScriptError.code();
+                    failed_tests += failed;
+                }
+
+                if junit_path.is_some() {
+                    let name = match current_script {
+                        Some(index) => options.script_paths[index - 1].display().to_string(),
+                        None => "run-in-roblox".to_owned(),
+                    };
+
+                    test_suites.push(TestSuite {
+                        name,
+                        passed,
+                        failed,
+                        skipped,
+                        failures: failures.clone(),
+                    });
+                }
+
+                if options.count_only {
+                    continue;
+                }
+
+                match options.format {
+                    OutputFormat::Human | OutputFormat::Junit => {
+                        for output in format_test_results(passed, failed, skipped, &failures) {
+                            print_output(output, &mut held_output, progress_bar.as_ref())?;
+                        }
+                    }
+                    OutputFormat::Json => {
+                        let output = json!({
+                            "test_results": {
+                                "passed": passed,
+                                "failed": failed,
+                                "skipped": skipped,
+                                "failures": failures,
+                            }
+                        });
+
+                        print_output(output.to_string(), &mut held_output, progress_bar.as_ref())?;
+                    }
+                }
+            }
+            RobloxMessage::Progress {
+                current,
+                total,
+                label,
+            } => {
+                if options.quiet {
+                    continue;
+                }
+
+                let label = label.unwrap_or_else(|| "Progress".to_owned());
+
+                if show_progress_bar {
+                    let progress_bar = progress_bar.get_or_insert_with(|| {
+                        let style = ProgressStyle::with_template("{msg} [{bar:40}] {pos}/{len}")
+                            .unwrap()
+                            .progress_chars("=> ");
+
+                        ProgressBar::new(total).with_style(style)
+                    });
+
+                    progress_bar.set_length(total);
+                    progress_bar.set_position(current);
+                    progress_bar.set_message(label);
+                } else {
+                    match options.format {
+                        OutputFormat::Human | OutputFormat::Junit => {
+                            // Only print a line every 10 percent so that we
+                            // don't flood logs.
+                            let percent = current
+                                .saturating_mul(100)
+                                .checked_div(total)
+                                .unwrap_or(100);
+
+                            if last_progress_percent.map_or(true, |last| percent / 10 > last / 10) {
+                                let output = format!("{}: {}%", label, percent);
+                                print_output(output, &mut held_output, None)?;
+                                last_progress_percent = Some(percent);
+                            }
+                        }
+                        OutputFormat::Json => {
+                            let output = json!({
+                                "progress": {
+                                    "current": current,
+                                    "total": total,
+                                    "label": label,
+                                }
+                            });
+
+                            print_output(output.to_string(), &mut held_output, None)?;
+                        }
+                    }
+                }
+
+                if current >= total {
+                    if let Some(progress_bar) = progress_bar.take() {
+                        progress_bar.finish_and_clear();
+                    }
+
+                    last_progress_percent = None;
+                }
+            }
+            // Heartbeats only exist to reset the timeout above.
+            RobloxMessage::Heartbeat => {}
+            RobloxMessage::StudioExited => {
+                log::error!("Roblox Studio exited unexpectedly");
+                studio_exited = true;
+            }
+            RobloxMessage::Exit { code } => {
+                requested_exit_code = Some(code);
+            }
+            RobloxMessage::Abort { reason } => {
+                log::error!("A script aborted the run: {}", reason);
+                aborted = true;
+                break;
+            }
+            RobloxMessage::Ready {
+                studio_version,
+                place_name,
+            } => {
+                log::info!("Roblox Studio {} opened {}", studio_version, place_name);
+            }
+            RobloxMessage::FileWrite {
+                relative_path,
+                contents,
+            } => match &options.artifact_dir {
+                Some(artifact_dir) => {
+                    if let Err(err) = write_artifact(artifact_dir, &relative_path, &contents) {
+                        log::error!("{:#}", err);
+                        exit_code = ExitCode::ScriptError.code();
+                        failed_file_writes += 1;
+                    }
+                }
+                None => log::warn!(
+                    "Ignoring file {} written by a script. Pass --artifact-dir to save it.",
+                    relative_path
+                ),
+            },
+            RobloxMessage::ScriptStart { index } => {
+                current_script = Some(index);
+
+                // Separators are only useful when there's more than one script
+                // to tell apart.
+                if options.script_paths.len() > 1 && !options.quiet {
+                    let script_path = options.script_paths[index - 1].display();
+
+                    let output = match options.format {
+                        OutputFormat::Human | OutputFormat::Junit => {
+                            format!("--- {} ---", script_path).bold().to_string()
+                        }
+                        OutputFormat::Json => {
+                            json!({ "script": script_path.to_string() }).to_string()
+                        }
+                    };
+
+                    print_output(output, &mut held_output, progress_bar.as_ref())?;
+                }
+            }
+            // Each run in watch mode starts from a clean slate, so the
+            // summary at the end only covers the latest version of the
+            // scripts.
+            RobloxMessage::ScriptsChanged => {
+                let run_exit_code = finish_exit_code(
+                    &options,
+                    exit_code,
+                    requested_exit_code,
+                    &counts,
+                    failed_tests + failed_file_writes,
+                );
+
+                // The last run is over, so show what --tail kept of it before
+                // holding back the next one.
+                if let Some(held_output) = held_output.take() {
+                    held_output.finish(
+                        run_exit_code != ExitCode::Success.code(),
+                        options.format,
+                        progress_bar.as_ref(),
+                    )?;
+                }
+                held_output = options.tail.map(TailBuffer::new);
+
+                counts = OutputCounts::default();
+                iteration_starts.clear();
+                printed_lines = 0;
+                output_truncated = false;
+                exit_code = ExitCode::Success.code();
+                requested_exit_code = None;
+                failed_tests = 0;
+                failed_file_writes = 0;
+
+                if !options.quiet {
+                    let output = match options.format {
+                        OutputFormat::Human | OutputFormat::Junit => {
+                            "=== Scripts changed, running again ===".bold().to_string()
+                        }
+                        OutputFormat::Json => json!({ "scripts_changed": true }).to_string(),
+                    };
+
+                    print_output(output, &mut held_output, progress_bar.as_ref())?;
+                }
+            }
+            RobloxMessage::IterationStart { iteration } => {
+                iteration_starts.push(counts);
+                printed_lines = 0;
+                output_truncated = false;
+
+                if options.repeat > 1 && !options.quiet {
+                    let output = match options.format {
+                        OutputFormat::Human | OutputFormat::Junit => {
+                            format!("=== Run {}/{} ===", iteration, options.repeat)
+                                .bold()
+                                .to_string()
+                        }
+                        OutputFormat::Json => json!({ "iteration": iteration }).to_string(),
+                    };
+
+                    print_output(output, &mut held_output, progress_bar.as_ref())?;
+                }
+            }
+        }
+
+        // Once the run has failed, there's no point in holding output back, so
+        // show everything that --tail has held and stream the rest.
+        if exit_code != ExitCode::Success.code() {
+            if let Some(held_output) = held_output.take() {
+                held_output.flush_all(progress_bar.as_ref())?;
+            }
+        }
+
+        // Make sure that anything reading our output through a pipe sees each
+        // message as soon as it arrives.
+        io::stdout().flush()?;
+    }
+
+    if let Some(progress_bar) = progress_bar {
+        progress_bar.finish_and_clear();
+    }
+
+    // Each iteration's counts are whatever changed between it starting and the
+    // next one starting.
+    let iterations = iteration_starts
+        .iter()
+        .enumerate()
+        .map(|(index, start)| {
+            let end = iteration_starts.get(index + 1).unwrap_or(&counts);
+            end.since(start)
+        })
+        .collect();
+
+    exit_code = finish_exit_code(
+        &options,
+        exit_code,
+        requested_exit_code,
+        &counts,
+        failed_tests + failed_file_writes,
+    );
+
+    if studio_exited {
+        exit_code = ExitCode::StudioCrash.code();
+    }
+
+    if aborted {
+        exit_code = ExitCode::Aborted.code();
+    }
+
+    // Ctrl-C is how --open and --watch are meant to end, so once the scripts
+    // have run, stopping them that way reports how the last run went.
+    if interrupted && !((options.open || options.watch) && heard_from_studio) {
+        exit_code = ExitCode::Interrupted.code();
+    }
+
+    if timed_out {
+        exit_code = ExitCode::Timeout.code();
+    }
+
+    // Only now do we know for sure whether the run passed, and so whether to
+    // show all of the output that --tail held back or just the end of it.
+    if let Some(held_output) = held_output {
+        held_output.finish(exit_code != ExitCode::Success.code(), options.format, None)?;
+    }
+
+    let runner_result = if interrupted && (options.stay_alive || options.open) {
+        // Studio is meant to outlive us, so leave the place runner waiting on
+        // it instead of cancelling it.
+        drop(receiver);
+
+        None
+    } else if timed_out || aborted || interrupted {
+        if timed_out {
+            log::error!(
+                "Timed out after {} seconds without hearing from Roblox Studio",
+                options.timeout.unwrap_or_default()
+            );
+        }
+
+        if interrupted {
+            log::warn!("Interrupted, closing Roblox Studio");
+        }
+
+        // The place runner is still waiting on Studio. Cancelling it makes it
+        // close Studio and remove the plugin, regardless of --stay-alive. We've
+        // stopped listening to it, so it's expected to fail to send whatever
+        // it had left, and there are no timings worth reporting.
+        runner_token.cancel();
+        drop(receiver);
+        let _ignored = place_runner_thread.join();
+
+        None
+    } else {
+        Some(
+            place_runner_thread
+                .join()
+                .map_err(|_| anyhow!("The place runner thread panicked"))?,
+        )
+    };
+
+    let runner_timings = runner_result.transpose()?;
+
+    let mut timings = vec![("Temp setup", temp_setup)];
+
+    if let Some(runner_timings) = runner_timings {
+        if options.warmup {
+            timings.push(("Warmup", runner_timings.warmup));
+        }
+
+        timings.push(("Plugin install", runner_timings.plugin_install));
+        timings.push(("Studio launch", runner_timings.studio_launch));
+        timings.push(("Plugin connect", runner_timings.plugin_connect));
+        timings.push(("Script run", runner_timings.script_run));
+    }
+
+    if !(options.stay_alive || options.open) || timed_out || aborted {
+        close_studio(&studio_session);
+    }
+
+    if let Some(junit_path) = &junit_path {
+        let report = BufWriter::new(fs::File::create(junit_path)?);
+        junit::write_report(
+            report,
+            &test_suites,
+            exit_code == ExitCode::Success.code(),
+            &junit_errors,
+            start_time.elapsed(),
+        )?;
+    }
+
+    if let Some(studio_log_dir) = &options.studio_log_dir {
+        if let Err(err) =
+            collect_studio_logs(studio_log_dir, options.wine_prefix.as_deref(), run_started)
+        {
+            log::warn!("Could not collect Roblox Studio's logs: {:#}", err);
+        }
+    }
+
+    Ok(RunSummary {
+        errors: counts.errors,
+        warnings: counts.warnings,
+        infos: counts.infos,
+        prints: counts.prints,
+        exit_code,
+        iterations,
+        timings,
+        longest_gap,
+    })
+}
+
+/// Writes a file from a script into the artifact folder. Scripts only get to
+/// choose a path inside of the folder, so that they can't overwrite anything
+/// else on the machine.
+pub fn write_artifact(
+    artifact_dir: &Path,
+    relative_path: &str,
+    contents: &str,
+) -> Result<(), anyhow::Error> {
+    let relative_path = Path::new(relative_path);
+    let stays_inside = relative_path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if !stays_inside || relative_path.as_os_str().is_empty() {
+        bail!(
+            "Refusing to write file {}, which is not a relative path inside the artifact folder",
+            relative_path.display()
+        );
+    }
+
+    let path = artifact_dir.join(relative_path);
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+
+    fs::write(&path, contents)?;
+    log::info!("Wrote {}", path.display());
+
+    Ok(())
+}
+
+/// Copies the log files that Roblox Studio wrote during this run into the
+/// given folder, so that they can be kept around after Studio cleans them up.
+fn collect_studio_logs(
+    studio_log_dir: &Path,
+    wine_prefix: Option<&Path>,
+    run_started: SystemTime,
+) -> Result<(), anyhow::Error> {
+    create_dir_all(studio_log_dir)?;
+
+    let mut copied = 0;
+
+    for log_dir in studio::log_dirs(wine_prefix) {
+        for entry in fs::read_dir(&log_dir)? {
+            let path = entry?.path();
+            let modified = fs::metadata(&path)?.modified()?;
+
+            if path.is_file() && modified >= run_started {
+                if let Some(file_name) = path.file_name() {
+                    fs::copy(&path, studio_log_dir.join(file_name))?;
+                    copied += 1;
+                }
+            }
+        }
+    }
+
+    log::info!(
+        "Copied {} Roblox Studio log file(s) to {}",
+        copied,
+        studio_log_dir.display()
+    );
+
+    Ok(())
+}
+
+/// Watches the scripts for changes, sending the new contents of every script
+/// each time one of them is saved.
+fn watch_scripts(script_paths: &[PathBuf]) -> Result<mpsc::Receiver<Vec<String>>, anyhow::Error> {
+    if script_paths
+        .iter()
+        .any(|script_path| script_path == Path::new("-"))
+    {
+        bail!("--watch can't be used with a script read from stdin");
+    }
+
+    let (event_sender, event_receiver) = mpsc::channel();
+    let mut watcher = notify::watcher(event_sender, WATCH_DEBOUNCE)?;
+
+    // Editors often save by replacing the file, which a watch on the file
+    // itself would miss, so watch the folders that the scripts are in instead.
+    let mut watched_paths = Vec::new();
+
+    for script_path in script_paths {
+        let script_path = std::fs::canonicalize(script_path)
+            .with_context(|| format!("Could not watch {}", script_path.display()))?;
+
+        if let Some(parent) = script_path.parent() {
+            watcher
+                .watch(parent, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Could not watch {}", parent.display()))?;
+        }
+
+        watched_paths.push(script_path);
+    }
+
+    let script_paths = script_paths.to_vec();
+    let (update_sender, update_receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        // The watcher stops watching once it's dropped.
+        let _watcher = watcher;
+
+        for event in event_receiver {
+            let changed_path = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => path,
+                _ => continue,
+            };
+
+            if !watched_paths.contains(&changed_path) {
+                continue;
+            }
+
+            log::info!("{} changed, running scripts again", changed_path.display());
+
+            let script_contents = script_paths
+                .iter()
+                .map(|script_path| read_script(script_path))
+                .collect::<Result<Vec<_>, _>>();
+
+            match script_contents {
+                Ok(script_contents) => {
+                    if update_sender.send(script_contents).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => log::error!("{:#}", err),
+            }
+        }
+    });
+
+    Ok(update_receiver)
+}
+
+/// Prints every copy of Studio that we can find, for `--list-studio`.
+fn list_studio_installs(wine_prefix: Option<&Path>) {
+    let installs = studio::all_installs(wine_prefix);
+
+    if installs.is_empty() {
+        println!("No Roblox Studio installations found.");
+        return;
+    }
+
+    for install in installs {
+        println!(
+            "{:<28} {:<10} {}  {}",
+            install.version.as_deref().unwrap_or("(unknown version)"),
+            install.channel.as_deref().unwrap_or("-"),
+            humantime::format_rfc3339_seconds(install.modified),
+            install.executable.display()
+        );
+    }
+}
+
+/// Makes sure that a file we copied can be written to. Copies keep the
+/// permissions of the original, and Studio hangs when the place it opens is
+/// read-only because it can't lock it.
+fn make_writable(path: &Path) -> Result<(), anyhow::Error> {
+    let mut permissions = fs::metadata(path)?.permissions();
+
+    if !permissions.readonly() {
+        return Ok(());
+    }
+
+    // Clearing the read-only flag on Unix would make the file writable by
+    // everyone, when only we need to write to it.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+
+    #[cfg(not(unix))]
+    permissions.set_readonly(false);
+
+    std::fs::set_permissions(path, permissions)
+        .with_context(|| format!("Could not make {} writable", path.display()))?;
+
+    Ok(())
+}
+
+/// Parses a `KEY=VALUE` pair for `--env`. Only the first `=` separates the
+/// key from the value, so values can contain `=` themselves.
+pub fn parse_env_var(source: &str) -> Result<(String, String), String> {
+    match source.split_once('=') {
+        Some((key, _value)) if key.is_empty() => {
+            Err(format!("Environment variable '{}' has no name", source))
+        }
+        Some((key, value)) => Ok((key.to_owned(), value.to_owned())),
+        None => Err(format!(
+            "Environment variable '{}' should be given as KEY=VALUE",
+            source
+        )),
+    }
+}
+
+/// Rejects ports that we'd fail to listen on with an unhelpful error from the
+/// OS, or that would defeat the point of choosing a port.
+pub fn validate_port(port: u16) -> Result<(), anyhow::Error> {
+    if port == 0 {
+        bail!(
+            "Port 0 can't be used with --port. If the port is taken, run-in-roblox already \
+             picks a free one on its own."
+        );
+    }
+
+    // Ports below 1024 are reserved for root on Unix-like systems.
+    #[cfg(unix)]
+    {
+        if port < 1024 && !is_root() {
+            bail!(
+                "Port {} is privileged and can only be used by root. \
+                 Choose a port of 1024 or higher with --port.",
+                port
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether we're running as root, which is allowed to listen on privileged
+/// ports.
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid takes no arguments, can't fail, and only reads the
+    // process's effective user ID.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// The extension of a place file, which is how we tell binary and XML places
+/// apart. Places without an extension are assumed to be binary places unless
+/// we're told otherwise.
+fn place_extension<'a>(
+    place_path: &'a Path,
+    place_format: Option<&'a str>,
+) -> Result<&'a str, anyhow::Error> {
+    let extension = match place_format {
+        Some(place_format) => place_format,
+        None => match place_path.extension() {
+            Some(extension) => extension
+                .to_str()
+                .ok_or_else(|| anyhow!("Place file extension had invalid Unicode"))?,
+            None => "rbxl",
+        },
+    };
+
+    // Studio will happily try to open a model as a place and then hang, so
+    // make sure we were actually given a place.
+    if extension != "rbxl" && extension != "rbxlx" {
+        bail!(
+            "Place file had unsupported extension '.{}'. Only .rbxl and .rbxlx places are supported.",
+            extension
+        );
+    }
+
+    Ok(extension)
+}
+
+/// The folder that each run's temp folder is created in.
+fn temp_folder_parent(temp_dir: Option<&Path>) -> Result<PathBuf, anyhow::Error> {
+    match temp_dir {
+        Some(temp_dir) => Ok(temp_dir.to_owned()),
+        None if cfg!(target_os = "linux") => linux_temp_dir(),
+        None => Ok(env::temp_dir()),
+    }
+}
+
+/// Picks where to put temporary places on Linux. We prefer `~/.tmp`, which is
+/// always reachable from wine, but minimal containers often don't set `HOME`.
+fn linux_temp_dir() -> Result<PathBuf, anyhow::Error> {
+    let candidates = [
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".tmp")),
+        env::var_os("XDG_RUNTIME_DIR")
+            .map(|runtime_dir| PathBuf::from(runtime_dir).join("run-in-roblox")),
+        Some(env::temp_dir()),
+    ];
+
+    candidates
+        .iter()
+        .flatten()
+        .find(|path| path.is_absolute())
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "Could not find a folder to put temporary places in. Choose one with --temp-dir."
+            )
+        })
+}
+
+/// Formats a table summarizing test results reported by a script, one line
+/// at a time.
+fn format_test_results(passed: u32, failed: u32, skipped: u32, failures: &[String]) -> Vec<String> {
+    let mut lines = vec![
+        String::new(),
+        "Test Results".bold().to_string(),
+        format!("  {:<8} {}", "Passed", passed.to_string().bright_green()),
+        format!(
+            "  {:<8} {}",
+            "Failed",
+            if failed == 0 {
+                failed.to_string().bright_green()
+            } else {
+                failed.to_string().bright_red()
+            }
+        ),
+        format!("  {:<8} {}", "Skipped", skipped.to_string().bright_yellow()),
+    ];
+
+    for failure in failures {
+        lines.push(format!("  {} {}", "FAIL".bright_red(), failure));
+    }
+
+    lines.push(String::new());
+    lines
+}
+
+/// Reads the contents of a script, treating a path of `-` as stdin.
+fn read_script(script_path: &Path) -> Result<String, anyhow::Error> {
+    let mut contents = Vec::new();
+
+    if script_path == Path::new("-") {
+        io::stdin().read_to_end(&mut contents)?;
+    } else {
+        contents = fs::read(script_path)?;
+    }
+
+    let contents = String::from_utf8(contents).map_err(|_| {
+        anyhow!(
+            "Script {} is not valid UTF-8. Scripts must be saved as UTF-8.",
+            script_path.display()
+        )
+    })?;
+
+    if contents.trim().is_empty() {
+        log::warn!("Script {} is empty", script_path.display());
+    }
+
+    // Scripts saved on Windows often have CRLF line endings, which can turn
+    // into confusing syntax errors once the script is in Studio. This only
+    // changes the copy that we send to Studio, not the user's file.
+    let contents = if contents.contains("\r\n") {
+        contents.replace("\r\n", "\n")
+    } else {
+        contents
+    };
+
+    Ok(contents)
+}
+
+/// The file name of the temporary place that Studio opens. It includes the
+/// server ID, which is unique to this run, so that `studio::close_studio` can pick out
+/// our Studio process even when other runs are going at the same time.
+fn temp_place_name(server_id: &str, extension: &str) -> String {
+    format!("{}.{}", server_id, extension)
+}
+
+/// Turns the name that Studio gives our copy of a script, like `...Scripts.2`,
+/// or `ServerScriptService.run-in-roblox-2.Main` in the server context, back
+/// into the path that the user gave for it. Other sources, like modules from
+/// the user's project, are left alone.
+fn script_source(source: String, script_paths: &[PathBuf]) -> String {
+    let index = match source.strip_suffix(".Main") {
+        Some(runner) => runner
+            .rsplit('.')
+            .next()
+            .and_then(|name| name.strip_prefix("run-in-roblox-")),
+        None => source
+            .rsplit_once('.')
+            .filter(|(parent, _)| *parent == "Scripts" || parent.ends_with(".Scripts"))
+            .map(|(_, name)| name),
+    };
+
+    let script_path = index
+        .and_then(|index| index.parse::<usize>().ok())
+        .and_then(|index| index.checked_sub(1))
+        .and_then(|index| script_paths.get(index));
+
+    match script_path {
+        Some(script_path) => script_path.display().to_string(),
+        None => source,
+    }
+}
+
+/// Settles the exit code of a run once its output is over. `exit_code` is what
+/// the output alone gave the run, which a script calling exit() or
+/// --fail-on-no-output and --exit-code-count can change.
+fn finish_exit_code(
+    options: &Options,
+    mut exit_code: i32,
+    requested_exit_code: Option<i32>,
+    counts: &OutputCounts,
+    other_failures: u32,
+) -> i32 {
+    if options.fail_on_no_output
+        && counts.prints == 0
+        && counts.warnings == 0
+        && counts.errors == 0
+        && requested_exit_code.is_none()
+    {
+        log::warn!("The scripts didn't produce any output. Did they run?");
+        exit_code = ExitCode::ScriptError.code();
+    }
+
+    if options.exit_code_count {
+        let mut failure_count = counts.errors + other_failures;
+
+        if options.warnings_as_errors {
+            failure_count += counts.warnings;
+        }
+
+        // A run can fail without anything to count, like with
+        // --fail-on-no-output, and that shouldn't turn into a pass.
+        if failure_count > 0 || exit_code == ExitCode::Success.code() {
+            exit_code = failure_count.min(125) as i32;
+        }
+    }
+
+    // A script that calls exit() knows better than we do whether it passed.
+    if let Some(code) = requested_exit_code {
+        exit_code = code;
+    }
+
+    exit_code
+}
+
+/// Prints a line of output above the progress bar, if there is one.
+fn print_line(output: &str, progress_bar: Option<&ProgressBar>) {
+    match progress_bar {
+        Some(progress_bar) => progress_bar.suspend(|| println!("{}", output)),
+        None => println!("{}", output),
+    }
+}
+
+/// Decides whether output at `level` should be shown, going by --quiet,
+/// --min-level, --grep, and how much of --max-output-lines has been used up.
+/// Output that is shown counts towards --max-output-lines.
+fn should_show_output(
+    options: &Options,
+    level: OutputLevel,
+    body: &str,
+    printed_lines: &mut u32,
+    output_truncated: &mut bool,
+) -> bool {
+    let show_output = level >= options.min_level
+        && !options.quiet
+        && options
+            .grep
+            .as_ref()
+            .map_or(true, |grep| grep.is_match(body));
+
+    if !show_output {
+        return false;
+    }
+
+    if let Some(max_output_lines) = options.max_output_lines {
+        if *printed_lines >= max_output_lines {
+            if !*output_truncated {
+                *output_truncated = true;
+                log::warn!(
+                    "Output truncated after {} lines. Later output is still counted.",
+                    max_output_lines
+                );
+            }
+
+            return false;
+        }
+    }
+
+    *printed_lines += 1;
+    true
+}
+
+/// Prints a line of output, or holds onto it for later if `--tail` is holding
+/// output back.
+fn print_output(
+    output: String,
+    held_output: &mut Option<TailBuffer>,
+    progress_bar: Option<&ProgressBar>,
+) -> Result<(), anyhow::Error> {
+    match held_output {
+        Some(held_output) => held_output.push(output)?,
+        None => print_line(&output, progress_bar),
+    }
+
+    Ok(())
+}
+
+/// The output that `--tail` is holding back. Only the last `capacity` lines
+/// are kept in memory, so a chatty run can't use up all of it. Older lines are
+/// spooled to a temporary file in case the run fails and they're needed after
+/// all.
+struct TailBuffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+    hidden: usize,
+    spool: Option<BufWriter<std::fs::File>>,
+}
+
+impl TailBuffer {
+    fn new(capacity: usize) -> Self {
+        TailBuffer {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+            hidden: 0,
+            spool: None,
+        }
+    }
+
+    fn push(&mut self, line: String) -> Result<(), anyhow::Error> {
+        self.lines.push_back(line);
+
+        if self.lines.len() > self.capacity {
+            if let Some(line) = self.lines.pop_front() {
+                let spool = match self.spool.take() {
+                    Some(spool) => spool,
+                    None => BufWriter::new(
+                        tempfile::tempfile()
+                            .context("Could not create a file to hold output for --tail")?,
+                    ),
+                };
+
+                writeln!(self.spool.insert(spool), "{}", line)?;
+            }
+
+            self.hidden += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Prints everything that was held back, including the lines that were
+    /// spooled, for a run that failed.
+    fn flush_all(self, progress_bar: Option<&ProgressBar>) -> Result<(), anyhow::Error> {
+        if let Some(spool) = self.spool {
+            let mut spool = spool.into_inner().map_err(|err| err.into_error())?;
+            spool.seek(SeekFrom::Start(0))?;
+
+            let mut copy_spool = || io::copy(&mut spool, &mut io::stdout().lock());
+
+            match progress_bar {
+                Some(progress_bar) => progress_bar.suspend(copy_spool)?,
+                None => copy_spool()?,
+            };
+        }
+
+        for line in &self.lines {
+            print_line(line, progress_bar);
+        }
+
+        Ok(())
+    }
+
+    /// Prints all of the output that was held back if the run failed, or only
+    /// its tail if it passed.
+    fn finish(
+        self,
+        failed: bool,
+        format: OutputFormat,
+        progress_bar: Option<&ProgressBar>,
+    ) -> Result<(), anyhow::Error> {
+        if failed {
+            self.flush_all(progress_bar)
+        } else {
+            self.flush_tail(format, progress_bar);
+            Ok(())
+        }
+    }
+
+    /// Prints only the lines that were kept, after a note saying how many
+    /// earlier lines were hidden, for a run that passed.
+    fn flush_tail(self, format: OutputFormat, progress_bar: Option<&ProgressBar>) {
+        if self.hidden > 0 && format != OutputFormat::Json {
+            let note = format!(
+                "... {} earlier line{} hidden by --tail",
+                self.hidden,
+                if self.hidden == 1 { "" } else { "s" }
+            );
+
+            print_line(&note.dimmed().to_string(), progress_bar);
+        }
+
+        for line in &self.lines {
+            print_line(line, progress_bar);
+        }
+    }
+}
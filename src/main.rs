@@ -1,155 +1,126 @@
-mod message_receiver;
-mod place_runner;
-mod plugin;
-
-use std::{env::home_dir, path::PathBuf, process, sync::mpsc, thread};
+use std::{
+    io::{self, Write},
+    process,
+};
 
-use anyhow::anyhow;
 use colored::Colorize;
-use fs_err::{self as fs, create_dir_all};
-use structopt::StructOpt;
-use sysinfo::System;
-use tempfile::{tempdir, tempdir_in};
-
-use crate::{
-    message_receiver::{OutputLevel, RobloxMessage},
-    place_runner::PlaceRunner,
+use run_in_roblox::{
+    apply_config_file, run_with_cancel_token, CancelToken, ExitCode, Options, OutputFormat,
+    RunSummary, PROTOCOL_VERSION,
 };
+use serde_json::json;
+use structopt::StructOpt;
 
-#[derive(Debug, StructOpt)]
-struct Options {
-    /// A path to the place file to open in Roblox Studio. If not specified, an
-    /// empty place file is used.
-    #[structopt(long("place"))]
-    place_path: Option<PathBuf>,
-
-    /// A path to the script to run in Roblox Studio.
-    ///
-    /// The script will be run at plugin-level security.
-    #[structopt(long("script"))]
-    script_path: PathBuf,
-    
-    /// Determines whether the Roblox Studio instance will stay open after the 
-    /// test concludes itself.
-    #[structopt(short("a"), long)]
-    stay_alive: bool,
-}
-
-fn run(options: Options) -> Result<i32, anyhow::Error> {
-    // Create a temp directory to house our place, even if a path is given from
-    // the command line. This helps ensure Studio won't hang trying to tell the
-    // user that the place is read-only because of a .lock file.
-    let temp_place_folder = 
-        if !cfg!(target_os = "linux") {
-            tempdir()?
-        }
-        else
-        {
-            let temp_dir_path = home_dir().unwrap().join(".tmp");
-            create_dir_all(&temp_dir_path)?;
-            tempdir_in(&temp_dir_path)?
-        };
-    let temp_place_path;
-
-    match &options.place_path {
-        Some(place_path) => {
-            let extension = place_path
-                .extension()
-                .ok_or_else(|| anyhow!("Place file did not have a file extension"))?
-                .to_str()
-                .ok_or_else(|| anyhow!("Place file extension had invalid Unicode"))?;
-
-            temp_place_path = temp_place_folder
-                .path()
-                .join(format!("run-in-roblox-place.{}", extension));
-
-            fs::copy(place_path, &temp_place_path)?;
-        }
-        None => {
-            unimplemented!("run-in-roblox with no place argument");
-        }
-    }
+/// Prints the counts and, if requested, the timings from the end of a run.
+fn print_summary(summary: &RunSummary, format: OutputFormat, show_timings: bool) {
+    match format {
+        OutputFormat::Human | OutputFormat::Junit => {
+            if summary.iterations.len() > 1 {
+                for (index, iteration) in summary.iterations.iter().enumerate() {
+                    eprint!("Run {}: ", index + 1);
+                    print_counts(
+                        iteration.errors,
+                        iteration.warnings,
+                        iteration.infos,
+                        iteration.prints,
+                    );
+                }
+            }
 
-    let script_contents = fs::read_to_string(&options.script_path)?;
+            // Lead with the outcome so that it's easy to spot in CI logs.
+            if summary.exit_code == ExitCode::Success.code() {
+                eprint!("{} ", "PASSED".bright_green().bold());
+            } else {
+                eprint!("{} ", "FAILED".bright_red().bold());
+            }
 
-    // Generate a random, unique ID for this session. The plugin we inject will
-    // compare this value with the one reported by the server and abort if they
-    // don't match.
-    let server_id = format!("run-in-roblox-{:x}", rand::random::<u128>());
+            if summary.iterations.len() > 1 {
+                eprint!("Total: ");
+            }
 
-    let place_runner = PlaceRunner {
-        port: 50312,
-        place_path: temp_place_path.clone(),
-        server_id: server_id.clone(),
-        lua_script: script_contents.clone(),
-    };
+            print_counts(
+                summary.errors,
+                summary.warnings,
+                summary.infos,
+                summary.prints,
+            );
 
-    let (sender, receiver) = mpsc::channel();
+            if show_timings {
+                eprintln!("{}", "Timings".bold());
 
-    thread::spawn(move || {
-        place_runner.run(sender).unwrap();
-    });
+                for (phase, duration) in &summary.timings {
+                    eprintln!("  {:<16} {}ms", phase, duration.as_millis());
+                }
 
-    let mut exit_code = 0;
-    
-    let mut warning_count = 0;
-    let mut error_count = 0;
-    let mut print_count = 0;
-
-    while let Some(message) = receiver.recv()? {
-        match message {
-            RobloxMessage::Output { level, body } => {
-                let colored_body = match level {
-                    OutputLevel::Print => body.normal(),
-                    OutputLevel::Info => body.cyan(),
-                    OutputLevel::Warning => body.yellow(),
-                    OutputLevel::Error => body.red(),
-                };
-
-                println!("{}", colored_body);
-
-                if level == OutputLevel::Error {
-                    exit_code = 1;
-                    error_count += 1;
-                } else if level == OutputLevel::Warning {
-                    warning_count += 1;
-                } else if level == OutputLevel::Print {
-                    print_count += 1;
+                if let Some(gap) = &summary.longest_gap {
+                    eprint!(
+                        "  {:<16} {}ms, starting at +{}ms",
+                        "Longest gap",
+                        gap.duration.as_millis(),
+                        gap.started_at.as_millis()
+                    );
+
+                    match &gap.after {
+                        Some(after) => eprintln!(" after \"{}\"", after),
+                        None => eprintln!(),
+                    }
                 }
             }
         }
-    }
-    
-    if !&options.stay_alive {
-        if !cfg!(target_os = "linux")
-        {
-            unimplemented!("run-in-roblox with stay-alive on non-linux pcs");
-        }
-        
-        let mut system = System::new_all();
-        system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
-        for process in system.processes_by_name("wine".as_ref()) {
-            let process_command = &process.cmd();
-            if process_command.len() <= 1 {
-                continue;
+        OutputFormat::Json => {
+            let mut output = json!({
+                "errors": summary.errors,
+                "warnings": summary.warnings,
+                "infos": summary.infos,
+                "prints": summary.prints,
+                "exit_code": summary.exit_code,
+            });
+
+            if summary.iterations.len() > 1 {
+                output["iterations"] = summary
+                    .iterations
+                    .iter()
+                    .map(|iteration| {
+                        json!({
+                            "errors": iteration.errors,
+                            "warnings": iteration.warnings,
+                            "infos": iteration.infos,
+                            "prints": iteration.prints,
+                        })
+                    })
+                    .collect();
             }
 
-            match &process_command[0] {
-                value if value.clone().into_string().unwrap().to_string().ends_with("RobloxStudioBeta.exe") => {
+            if show_timings {
+                let timings: serde_json::Map<_, _> = summary
+                    .timings
+                    .iter()
+                    .map(|(phase, duration)| {
+                        let key = format!("{}_ms", phase.to_lowercase().replace(' ', "_"));
+                        (key, json!(duration.as_millis() as u64))
+                    })
+                    .collect();
+
+                output["timings"] = timings.into();
+
+                if let Some(gap) = &summary.longest_gap {
+                    output["longest_gap"] = json!({
+                        "duration_ms": gap.duration.as_millis() as u64,
+                        "started_at_ms": gap.started_at.as_millis() as u64,
+                        "after": gap.after,
+                    });
+                }
+            }
 
-                    if process_command[1].clone().into_string().unwrap().to_string().contains("run-in-roblox-place")
-                    {
-                        process.kill_with(sysinfo::Signal::Term);
-                    }
-                },
-                _ => ()
-            };
+            println!("{}", output);
         }
     }
+}
 
-    
-    print!(
-        "{} error{}, {} warning{}, and {} print{}.", 
+/// Prints a line summarizing how many of each kind of output there were.
+fn print_counts(error_count: u32, warning_count: u32, info_count: u32, print_count: u32) {
+    eprintln!(
+        "{} error{}, {} warning{}, {} info{}, and {} print{}.",
         if error_count == 0 {
             error_count.to_string().bright_green()
         } else {
@@ -162,31 +133,99 @@ fn run(options: Options) -> Result<i32, anyhow::Error> {
             warning_count.to_string().bright_yellow()
         },
         if warning_count == 1 { "" } else { "s" },
+        if info_count == 0 {
+            info_count.to_string().bright_green()
+        } else {
+            info_count.to_string().bright_cyan()
+        },
+        if info_count == 1 { "" } else { "s" },
         if print_count == 0 {
             print_count.to_string().bright_green()
-        } else { print_count.to_string().bright_white() },
+        } else {
+            print_count.to_string().bright_white()
+        },
         if print_count == 1 { "" } else { "s" }
     );
-    
-    Ok(exit_code)
 }
 
 fn main() {
-    let options = Options::from_args();
+    // A plugin left behind by an older version can cause confusing failures,
+    // so say which plugin protocol this version speaks.
+    let version = format!(
+        "{} (plugin protocol {})",
+        env!("CARGO_PKG_VERSION"),
+        PROTOCOL_VERSION
+    );
+    let exit_codes = ExitCode::help();
+    let matches = Options::clap()
+        .version(version.as_str())
+        .after_help(exit_codes.as_str())
+        .get_matches();
+    let mut options = Options::from_clap(&matches);
 
     {
-        let log_env = env_logger::Env::default().default_filter_or("warn");
+        let log_filter = match options.verbose {
+            0 => "warn",
+            1 => "warn,run_in_roblox=info",
+            2 => "warn,run_in_roblox=debug",
+            _ => "warn,run_in_roblox=trace",
+        };
+        let log_env = env_logger::Env::default().default_filter_or(log_filter);
 
         env_logger::Builder::from_env(log_env)
             .format_timestamp(None)
             .init();
     }
 
-    match run(options) {
-        Ok(exit_code) => process::exit(exit_code),
+    if let Err(err) = apply_config_file(&mut options, &matches) {
+        log::error!("{:?}", err);
+        process::exit(ExitCode::HostError.code());
+    }
+
+    // The first Ctrl-C stops the run, which closes Studio. If that's what's
+    // stuck, a second one stops us right away.
+    let cancel_token = CancelToken::new();
+    let handler_token = cancel_token.clone();
+    let handler_result = ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            process::exit(ExitCode::Interrupted.code());
+        }
+
+        handler_token.cancel();
+    });
+
+    if let Err(err) = handler_result {
+        log::error!("Could not set up the Ctrl-C handler: {}", err);
+        process::exit(ExitCode::HostError.code());
+    }
+
+    let format = options.format;
+    let show_timings = options.timings;
+    let count_only = options.count_only;
+    // None of these run any scripts, so there's nothing to sum up, not even
+    // with --count-only.
+    let show_summary = !(options.install_plugin_only
+        || options.dry_run
+        || options.print_plugin
+        || options.list_studio);
+
+    match run_with_cancel_token(options, cancel_token) {
+        Ok(summary) => {
+            if show_summary {
+                if count_only {
+                    println!("{} {} {}", summary.errors, summary.warnings, summary.prints);
+                } else {
+                    print_summary(&summary, format, show_timings);
+                }
+            }
+
+            let _ignored = io::stdout().flush();
+
+            process::exit(summary.exit_code)
+        }
         Err(err) => {
             log::error!("{:?}", err);
-            process::exit(2);
+            process::exit(ExitCode::HostError.code());
         }
     }
 }
@@ -1,18 +1,38 @@
 use std::{
-    sync::{mpsc, Arc},
+    io::{self, Read},
+    net::{IpAddr, TcpListener},
+    str::FromStr,
+    sync::{mpsc, Arc, RwLock},
     thread,
     time::Duration,
 };
 
+use colored::Color;
+use flate2::read::GzDecoder;
 use futures::{future, stream::Stream, sync::oneshot, Future};
-use hyper::{service::service_fn, Body, Method, Request, Response, Server, StatusCode};
-use serde::Deserialize;
+use hyper::{
+    header::CONTENT_ENCODING, service::service_fn, Body, Method, Request, Response, Server,
+    StatusCode,
+};
+use serde::{Deserialize, Deserializer};
+
+use crate::plugin::PROTOCOL_VERSION;
+
+/// The header that the plugin uses to send the server ID back to us with each
+/// request.
+pub const SERVER_ID_HEADER: &str = "X-Run-In-Roblox-Server-Id";
 
 type HyperResponse = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Start,
+
+    /// The plugin connected, but it speaks a different version of the
+    /// protocol than we do, so nothing it sends can be trusted to parse.
+    ProtocolMismatch {
+        plugin_version: Option<u32>,
+    },
     Stop,
     Messages(Vec<RobloxMessage>),
 }
@@ -20,10 +40,93 @@ pub enum Message {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum RobloxMessage {
-    Output { level: OutputLevel, body: String },
+    Output {
+        level: OutputLevel,
+        body: String,
+
+        /// The script that produced this message, if known.
+        source: Option<String>,
+
+        /// The line in `source` that produced this message, if known.
+        line: Option<u32>,
+
+        /// How many seconds after the plugin started that Studio logged this
+        /// message. Plugins from before this was added don't send it.
+        studio_time: Option<f64>,
+
+        /// Which part of the session logged this message: `edit` while the
+        /// place is being edited, or `server` or `client` once the game is
+        /// running. Plugins from before this was added don't send it.
+        context: Option<String>,
+    },
+    Exit {
+        code: i32,
+    },
+
+    /// Sent when a script calls `abort(reason)` to stop the run right away.
+    Abort {
+        reason: String,
+    },
+
+    /// Sent once the place has finished loading, before any scripts run.
+    Ready {
+        studio_version: String,
+        place_name: String,
+    },
+    ScriptStart {
+        index: usize,
+    },
+
+    /// Sent before each pass through the scripts when `--repeat` is used.
+    IterationStart {
+        iteration: u32,
+    },
+    TestResults {
+        passed: u32,
+        failed: u32,
+        skipped: u32,
+
+        /// The names of the tests that failed.
+        #[serde(default)]
+        failures: Vec<String>,
+    },
+
+    /// How far along a long-running script is.
+    Progress {
+        current: u64,
+        total: u64,
+        label: Option<String>,
+    },
+
+    /// A file that a script wants saved into the artifact folder.
+    FileWrite {
+        relative_path: String,
+        contents: String,
+    },
+
+    /// An error that stopped a script, along with where it happened.
+    ScriptError {
+        message: String,
+        traceback: String,
+    },
+
+    /// Sent in watch mode when the plugin is about to run scripts again
+    /// because they changed.
+    ScriptsChanged,
+
+    /// Sent regularly by the plugin while a script is running to show that
+    /// Studio hasn't hung.
+    Heartbeat,
+
+    /// Sent by the place runner, not the plugin, when Studio exits before the
+    /// plugin says that it's done.
+    #[serde(skip)]
+    StudioExited,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+/// The level of a message logged in Roblox Studio, ordered from least to most
+/// severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum OutputLevel {
     Print,
     Info,
@@ -31,57 +134,300 @@ pub enum OutputLevel {
     Error,
 }
 
+impl OutputLevel {
+    /// Classifies output by the value of the `Enum.MessageType` that
+    /// LogService gave it: `print` is `MessageOutput`, `warn` is
+    /// `MessageWarning`, and errors are `MessageError`. Anything else is shown
+    /// as info, like `MessageInfo` is.
+    pub fn from_message_type(message_type: u32) -> Self {
+        match message_type {
+            0 => OutputLevel::Print,
+            2 => OutputLevel::Warning,
+            3 => OutputLevel::Error,
+            _ => OutputLevel::Info,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutputLevel::Print => "print",
+            OutputLevel::Info => "info",
+            OutputLevel::Warning => "warning",
+            OutputLevel::Error => "error",
+        }
+    }
+
+    /// The color that output at this level is shown in, or `None` if it should
+    /// use the terminal's default color.
+    pub fn color(self) -> Option<Color> {
+        match self {
+            OutputLevel::Print => None,
+            OutputLevel::Info => Some(Color::Cyan),
+            OutputLevel::Warning => Some(Color::Yellow),
+            OutputLevel::Error => Some(Color::Red),
+        }
+    }
+}
+
+impl FromStr for OutputLevel {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "print" => Ok(OutputLevel::Print),
+            "info" => Ok(OutputLevel::Info),
+            "warning" => Ok(OutputLevel::Warning),
+            "error" => Ok(OutputLevel::Error),
+            _ => Err(format!("Unknown output level '{}'", source)),
+        }
+    }
+}
+
+/// The plugin sends the raw `Enum.MessageType` value of each piece of output
+/// so that it's classified in one place, but levels can also be given by name.
+impl<'de> Deserialize<'de> for OutputLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawLevel {
+            MessageType(u32),
+            Name(String),
+        }
+
+        match RawLevel::deserialize(deserializer)? {
+            RawLevel::MessageType(message_type) => Ok(OutputLevel::from_message_type(message_type)),
+            RawLevel::Name(name) => match name.as_str() {
+                "Print" => Ok(OutputLevel::Print),
+                "Info" => Ok(OutputLevel::Info),
+                "Warning" => Ok(OutputLevel::Warning),
+                "Error" => Ok(OutputLevel::Error),
+                _ => Err(serde::de::Error::unknown_variant(
+                    &name,
+                    &["Print", "Info", "Warning", "Error"],
+                )),
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MessageReceiverOptions {
+    /// The address to listen on, which is usually a loopback address.
+    pub bind_address: IpAddr,
     pub port: u16,
     pub server_id: String,
+
+    /// The scripts that the plugin should run, which it fetches from us
+    /// instead of having them baked into the plugin.
+    pub scripts: Vec<String>,
+
+    /// How many requests from the plugin can be waiting to be received before
+    /// we stop answering new ones. This keeps a script that prints faster than
+    /// we can keep up from using up all of our memory.
+    pub channel_capacity: usize,
+}
+
+/// The body of the request that the plugin makes when it starts.
+#[derive(Debug, Deserialize)]
+struct StartRequest {
+    protocol_version: u32,
+}
+
+/// The scripts that the plugin fetches, along with how many times they've been
+/// replaced so that the plugin can tell when to fetch them again.
+#[derive(Debug)]
+struct ScriptStore {
+    revision: u32,
+    scripts: Vec<String>,
 }
 
 pub struct MessageReceiver {
+    port: u16,
     shutdown_tx: oneshot::Sender<()>,
     message_rx: mpsc::Receiver<Message>,
+    scripts: Arc<RwLock<ScriptStore>>,
 }
 
 impl MessageReceiver {
-    pub fn start(options: MessageReceiverOptions) -> MessageReceiver {
-        let (message_tx, message_rx) = mpsc::channel();
+    /// Binds to the requested port and starts listening for messages from the
+    /// plugin on a background thread.
+    ///
+    /// Binding happens before this function returns so that a port that is
+    /// already in use is reported to the caller instead of panicking the server
+    /// thread.
+    pub fn start(options: MessageReceiverOptions) -> io::Result<MessageReceiver> {
+        let listener = TcpListener::bind((options.bind_address, options.port))?;
+        let port = listener.local_addr()?.port();
+
+        let (message_tx, message_rx) = mpsc::sync_channel(options.channel_capacity);
         let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
         let server_id = Arc::new(options.server_id.clone());
+        let scripts = Arc::new(RwLock::new(ScriptStore {
+            revision: 0,
+            scripts: options.scripts,
+        }));
+        let receiver_scripts = scripts.clone();
 
         thread::spawn(move || {
             let service = move || {
                 let server_id = server_id.clone();
+                let scripts = scripts.clone();
                 let message_tx = message_tx.clone();
 
                 service_fn(move |request: Request<Body>| -> HyperResponse {
                     let server_id = server_id.clone();
+                    let scripts = scripts.clone();
                     let message_tx = message_tx.clone();
                     let mut response = Response::new(Body::empty());
 
                     log::debug!("Request: {} {}", request.method(), request.uri().path());
 
+                    // Every request needs to prove that it came from our
+                    // plugin, so that other local processes can't feed us fake
+                    // output or learn the server ID from the ping.
+                    let authorized = request
+                        .headers()
+                        .get(SERVER_ID_HEADER)
+                        .map(|value| value.as_bytes() == server_id.as_bytes())
+                        .unwrap_or(false);
+
                     match (request.method(), request.uri().path()) {
+                        (_, path) if !authorized => {
+                            log::warn!(
+                                "Rejected request to {} with a missing or incorrect server ID",
+                                path
+                            );
+                            *response.status_mut() = StatusCode::FORBIDDEN;
+                        }
                         (&Method::GET, "/") => {
                             *response.body_mut() = Body::from(server_id.as_str().to_owned());
                         }
+                        (&Method::GET, path) if path.starts_with("/scripts/") => {
+                            // Scripts are numbered from 1, in the order they
+                            // should be run.
+                            let store = scripts.read().unwrap();
+                            let script = path["/scripts/".len()..]
+                                .parse::<usize>()
+                                .ok()
+                                .and_then(|index| index.checked_sub(1))
+                                .and_then(|index| store.scripts.get(index));
+
+                            match script {
+                                Some(script) => *response.body_mut() = Body::from(script.clone()),
+                                None => *response.status_mut() = StatusCode::NOT_FOUND,
+                            }
+                        }
+                        (&Method::GET, "/revision") => {
+                            let revision = scripts.read().unwrap().revision;
+                            *response.body_mut() = Body::from(revision.to_string());
+                        }
                         (&Method::POST, "/start") => {
-                            message_tx.send(Message::Start).unwrap();
-                            *response.body_mut() = Body::from("Started");
+                            let message_tx = message_tx.clone();
+
+                            // The plugin says which protocol version it speaks,
+                            // and we answer with ours so that it can check too.
+                            let future = request.into_body().concat2().map(move |chunk| {
+                                let plugin_version = serde_json::from_slice::<StartRequest>(&chunk)
+                                    .ok()
+                                    .map(|start| start.protocol_version);
+
+                                let message = if plugin_version == Some(PROTOCOL_VERSION) {
+                                    Message::Start
+                                } else {
+                                    Message::ProtocolMismatch { plugin_version }
+                                };
+
+                                if message_tx.send(message).is_err() {
+                                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                                    return response;
+                                }
+
+                                *response.body_mut() = Body::from(PROTOCOL_VERSION.to_string());
+                                response
+                            });
+
+                            return Box::new(future);
                         }
                         (&Method::POST, "/stop") => {
-                            message_tx.send(Message::Stop).unwrap();
-                            *response.body_mut() = Body::from("Finished");
+                            if message_tx.send(Message::Stop).is_ok() {
+                                *response.body_mut() = Body::from("Finished");
+                            } else {
+                                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                            }
                         }
                         (&Method::POST, "/messages") => {
                             let message_tx = message_tx.clone();
 
+                            // With --compress, the plugin has Roblox gzip its
+                            // messages.
+                            let is_gzipped = request
+                                .headers()
+                                .get(CONTENT_ENCODING)
+                                .map_or(false, |value| {
+                                    value.as_bytes().eq_ignore_ascii_case(b"gzip")
+                                });
+
                             let future = request.into_body().concat2().map(move |chunk| {
-                                let source = chunk.to_vec();
-                                let messages: Vec<RobloxMessage> = serde_json::from_slice(&source)
-                                    .expect("Failed deserializing message from Roblox Studio");
+                                let source = if is_gzipped {
+                                    let mut decoded = Vec::new();
 
-                                message_tx.send(Message::Messages(messages)).unwrap();
+                                    if let Err(err) =
+                                        GzDecoder::new(&chunk[..]).read_to_end(&mut decoded)
+                                    {
+                                        log::error!(
+                                            "Could not decompress messages from Roblox Studio: {}",
+                                            err
+                                        );
+                                        *response.status_mut() = StatusCode::BAD_REQUEST;
+                                        return response;
+                                    }
+
+                                    decoded
+                                } else {
+                                    chunk.to_vec()
+                                };
+
+                                let values: Vec<serde_json::Value> =
+                                    match serde_json::from_slice(&source) {
+                                        Ok(values) => values,
+                                        Err(err) => {
+                                            log::error!(
+                                                "Could not read messages from Roblox Studio: {}",
+                                                err
+                                            );
+                                            *response.status_mut() = StatusCode::BAD_REQUEST;
+                                            return response;
+                                        }
+                                    };
+
+                                // One message that we can't make sense of, like
+                                // progress given as a fraction, shouldn't take
+                                // the rest of the batch down with it.
+                                let messages = values
+                                    .into_iter()
+                                    .filter_map(|value| {
+                                        match serde_json::from_value::<RobloxMessage>(value) {
+                                            Ok(message) => Some(message),
+                                            Err(err) => {
+                                                log::warn!(
+                                                    "Skipping a message from Roblox Studio that \
+                                                     could not be read: {}",
+                                                    err
+                                                );
+                                                None
+                                            }
+                                        }
+                                    })
+                                    .collect();
+
+                                // Once the run is over, nothing is listening
+                                // for messages anymore. That's no reason for
+                                // the server to panic.
+                                if message_tx.send(Message::Messages(messages)).is_err() {
+                                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                                    return response;
+                                }
 
                                 *response.body_mut() = Body::from("Got it!");
                                 response
@@ -98,19 +444,40 @@ impl MessageReceiver {
                 })
             };
 
-            let addr = ([127, 0, 0, 1], options.port).into();
-            let server = Server::bind(&addr)
-                .serve(service)
-                .with_graceful_shutdown(shutdown_rx)
-                .map_err(|e| eprintln!("server error: {}", e));
+            let server = future::lazy(move || {
+                Server::from_tcp(listener)
+                    .map_err(|e| eprintln!("server error: {}", e))
+                    .map(|builder| {
+                        builder
+                            .serve(service)
+                            .with_graceful_shutdown(shutdown_rx)
+                            .map_err(|e| eprintln!("server error: {}", e))
+                    })
+            })
+            .flatten();
 
             hyper::rt::run(server);
         });
 
-        MessageReceiver {
+        Ok(MessageReceiver {
+            port,
             shutdown_tx,
             message_rx,
-        }
+            scripts: receiver_scripts,
+        })
+    }
+
+    /// The port that the receiver is actually listening on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Replaces the scripts that the plugin fetches. A plugin that's watching
+    /// for changes will notice and run the new scripts.
+    pub fn set_scripts(&self, scripts: Vec<String>) {
+        let mut store = self.scripts.write().unwrap();
+        store.revision += 1;
+        store.scripts = scripts;
     }
 
     pub fn recv(&self) -> Message {
@@ -0,0 +1,81 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+
+use rbx_binary::EncodeError;
+use rbx_dom_weak::{RbxInstanceProperties, RbxTree};
+
+/// Writes a binary place file that contains nothing. Roblox Studio fills in
+/// all of the default services when it opens a place like this, which makes it
+/// a good stand-in when the user doesn't give us a place of their own.
+pub fn write_empty_place<W: Write>(output: W) -> Result<(), EncodeError> {
+    let tree = RbxTree::new(RbxInstanceProperties {
+        name: "DataModel".to_owned(),
+        class_name: "DataModel".to_owned(),
+        properties: HashMap::new(),
+    });
+
+    rbx_binary::encode(&tree, &[], output)
+}
+
+/// Converts an XML place into a binary place.
+pub fn convert_xml_to_binary<R: Read, W: Write>(input: R, output: W) -> Result<(), anyhow::Error> {
+    let tree = rbx_xml::from_reader_default(input)?;
+
+    let root = tree.get_instance(tree.get_root_id()).unwrap();
+    rbx_binary::encode(&tree, root.get_children_ids(), output)?;
+
+    Ok(())
+}
+
+/// The extension of the place that a URL points to, which is `rbxl` if the
+/// URL doesn't have one.
+pub fn url_extension(url: &str) -> Result<&str, anyhow::Error> {
+    // Ignore any query string or fragment when looking for the extension.
+    let url_path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+    let extension = Path::new(url_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("rbxl");
+
+    if extension != "rbxl" && extension != "rbxlx" {
+        bail!(
+            "Place URL had unsupported extension '.{}'. Only .rbxl and .rbxlx places are supported.",
+            extension
+        );
+    }
+
+    Ok(extension)
+}
+
+/// Downloads a place from the given URL into `folder`, returning the path to
+/// the downloaded file.
+pub fn download_place(url: &str, folder: &Path) -> Result<PathBuf, anyhow::Error> {
+    let extension = url_extension(url)?;
+
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("Could not download place from {}", url))?;
+
+    // Servers tend to answer with an HTML page when something goes wrong, so
+    // make sure we didn't get one of those instead of a place.
+    if response.content_type().starts_with("text/html") {
+        bail!(
+            "Downloading place from {} returned an HTML page instead of a place file",
+            url
+        );
+    }
+
+    let download_path = folder.join(format!("downloaded-place.{}", extension));
+    let mut file = fs::File::create(&download_path)?;
+
+    io::copy(&mut response.into_reader(), &mut file)
+        .with_context(|| format!("Could not download place from {}", url))?;
+
+    Ok(download_path)
+}
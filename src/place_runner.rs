@@ -1,5 +1,15 @@
 use std::{
-    path::{PathBuf}, process::{self, Command, Stdio}, sync::mpsc, time::Duration
+    env,
+    io::{self, BufRead, BufReader, Read},
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context};
@@ -9,9 +19,34 @@ use roblox_install::RobloxStudio;
 
 use crate::{
     message_receiver::{Message, MessageReceiver, MessageReceiverOptions, RobloxMessage},
-    plugin::RunInRbxPlugin,
+    plugin::{self, RunInRbxPlugin, ScriptContext},
+    project::ProjectNode,
+    studio::{self, StudioSession},
+    KillSignal,
 };
 
+/// The start of the name of every plugin file we install, which is how we
+/// recognize plugins left behind by earlier runs.
+const PLUGIN_FILE_PREFIX: &str = "run_in_roblox-";
+
+/// How many messages can be waiting to be received by default. This is
+/// plenty to smooth out bursts of output without using much memory.
+const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+/// How often to log that we're still waiting for the plugin to connect.
+const CONNECT_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait before the first retry of a failed launch. Each retry
+/// after that waits a little longer.
+const LAUNCH_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long a warmup launch leaves Studio running before closing it, which is
+/// long enough for wine to finish setting up its prefix.
+const WARMUP_DURATION: Duration = Duration::from_secs(10);
+
+/// How long to give Studio to exit after asking it to before killing it.
+const STUDIO_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// A wrapper for process::Child that force-kills the process on drop.
 struct KillOnDrop(process::Child);
 
@@ -21,103 +56,975 @@ impl Drop for KillOnDrop {
     }
 }
 
+/// Logs each line that Studio writes to one of its output streams.
+fn forward_studio_output<R: Read + Send + 'static>(stream_name: &'static str, output: R) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => log::debug!("Studio {}: {}", stream_name, line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+/// A handle for cancelling a run from another thread. Cancelling closes Studio
+/// and makes `PlaceRunner::run` return soon after.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// How long each part of a run took, as seen by the place runner.
+#[derive(Debug, Clone, Default)]
+pub struct RunTimings {
+    pub warmup: Duration,
+    pub plugin_install: Duration,
+    pub studio_launch: Duration,
+    pub plugin_connect: Duration,
+    pub script_run: Duration,
+}
+
 pub struct PlaceRunner {
+    /// The address to listen for the plugin on.
+    pub bind_address: IpAddr,
     pub port: u16,
     pub place_path: PathBuf,
     pub server_id: String,
-    pub lua_script: String,
-}
+    pub lua_scripts: Vec<String>,
+    pub args: Vec<String>,
+    pub fail_fast: bool,
+
+    /// How many times to run the scripts in a row.
+    pub repeat: u32,
+
+    pub studio_path: Option<PathBuf>,
+
+    /// The release channel that the version of Studio used on Windows has to
+    /// come from, if it matters.
+    pub studio_channel: Option<String>,
+
+    /// Extra arguments to launch Studio with, after the place path.
+    pub studio_args: Vec<String>,
+
+    /// Extra environment variables to launch Studio with.
+    pub studio_env: Vec<(String, String)>,
+
+    pub wine_prefix: Option<PathBuf>,
+    pub keep_open: bool,
+
+    /// Where and how the plugin runs the scripts.
+    pub context: ScriptContext,
+
+    pub project: Option<ProjectNode>,
+
+    /// How long to wait for the plugin to connect after launching Studio.
+    pub connect_timeout: Duration,
+
+    /// How many times to try launching Studio again if it exits right away or
+    /// the plugin never connects.
+    pub launch_retries: u32,
+
+    /// Whether to remove plugins left behind by earlier runs before installing
+    /// ours.
+    pub clean_plugins: bool,
+
+    /// Whether the plugin should compress the messages it sends us.
+    pub compress: bool,
+
+    /// New versions of the scripts to run in watch mode. When this is set,
+    /// the plugin keeps running and runs each new version as it arrives.
+    pub script_updates: Option<mpsc::Receiver<Vec<String>>>,
 
-fn generate_studio_process(place_runner: &PlaceRunner, studio_install: &RobloxStudio) -> Result<KillOnDrop, anyhow::Error> {
-    
-    Ok(KillOnDrop(
-        Command::new(studio_install.application_path())
-            .arg(format!("{}", &place_runner.place_path.display()))
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?
-    ))
+    /// How many messages can be waiting to be received at each step between
+    /// the plugin and whoever is receiving them from us. Once that many are
+    /// waiting, the plugin is made to wait too.
+    pub channel_capacity: usize,
+
+    /// The source of a plugin to install instead of our own. Placeholders
+    /// like `{{PORT}}` in it are filled in the same way as in ours.
+    pub plugin_template: Option<String>,
+
+    /// Whether to launch Studio through `xvfb-run`, for Linux machines
+    /// without a display.
+    pub xvfb: bool,
+
+    /// Whether to launch Studio once and close it again before the real run,
+    /// so that the real run doesn't pay for a cold wine prefix.
+    pub warmup: bool,
+
+    /// How to stop Studio when the run is cancelled or after warming up.
+    pub kill_signal: KillSignal,
+
+    /// The names of Studio's executable, for finding the copy of Studio that
+    /// we launched. When empty, the names Studio uses on this platform are
+    /// used.
+    pub studio_executable_names: Vec<String>,
+
+    /// Lets another thread stop the run early.
+    pub cancel_token: CancelToken,
 }
 
 impl PlaceRunner {
-    pub fn run(&self, sender: mpsc::Sender<Option<RobloxMessage>>) -> Result<(), anyhow::Error> {
-        let studio_install =
-            RobloxStudio::locate().context("Could not locate a Roblox Studio installation.")?;
+    /// Starts building a runner with a new server ID, any free port, and no
+    /// scripts.
+    pub fn builder() -> PlaceRunnerBuilder {
+        PlaceRunnerBuilder {
+            runner: PlaceRunner {
+                bind_address: Ipv4Addr::LOCALHOST.into(),
+                port: 0,
+                place_path: PathBuf::new(),
+                server_id: generate_server_id(),
+                lua_scripts: Vec::new(),
+                args: Vec::new(),
+                fail_fast: false,
+                repeat: 1,
+                studio_path: None,
+                studio_channel: None,
+                studio_args: Vec::new(),
+                studio_env: Vec::new(),
+                wine_prefix: None,
+                keep_open: false,
+                context: ScriptContext::Plugin,
+                project: None,
+                connect_timeout: Duration::from_secs(60),
+                launch_retries: 0,
+                clean_plugins: false,
+                compress: false,
+                script_updates: None,
+                channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+                plugin_template: None,
+                xvfb: false,
+                warmup: false,
+                kill_signal: KillSignal::Term,
+                studio_executable_names: Vec::new(),
+                cancel_token: CancelToken::new(),
+            },
+        }
+    }
 
-        let plugin_file_path = studio_install
-            .plugins_path()
-            .join(format!("run_in_roblox-{}.rbxmx", self.port));
+    /// Returns a handle that can cancel this runner's run from another thread.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel_token.clone()
+    }
 
-        let plugin = RunInRbxPlugin {
-            port: self.port,
-            server_id: &self.server_id,
-            lua_script: &self.lua_script,
-        };
+    /// What we need to find the copy of Studio that this runner launched,
+    /// which is usually not the process we spawned: the Studio launcher and
+    /// wine both hand off to another process.
+    pub(crate) fn studio_session(&self, kill_signal: KillSignal) -> StudioSession {
+        StudioSession {
+            server_id: self.server_id.clone(),
+            wine_prefix: self.wine_prefix.clone(),
+            kill_signal,
+            executable_names: if self.studio_executable_names.is_empty() {
+                studio::default_studio_executable_names()
+            } else {
+                self.studio_executable_names.clone()
+            },
+        }
+    }
 
-        let plugin_file = File::create(&plugin_file_path)?;
-        plugin.write(plugin_file)?;
+    /// Closes the copy of Studio that this runner launched, then waits for the
+    /// process we spawned to exit, killing it if it takes too long.
+    fn close_studio(
+        &self,
+        studio_process: &mut KillOnDrop,
+        kill_signal: KillSignal,
+    ) -> Result<(), anyhow::Error> {
+        studio::close_studio(&self.studio_session(kill_signal));
 
-        let message_receiver = MessageReceiver::start(MessageReceiverOptions {
-            port: self.port,
+        let phase_start = Instant::now();
+
+        while studio_process.0.try_wait()?.is_none() {
+            if phase_start.elapsed() >= STUDIO_CLOSE_TIMEOUT {
+                let _ignored = studio_process.0.kill();
+                studio_process.0.wait()?;
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(())
+    }
+
+    /// Starts listening for messages from the plugin on the requested port. If
+    /// that port is already taken, we ask the OS for a free one instead so that
+    /// several runs can happen side by side.
+    fn start_message_receiver(&self) -> Result<MessageReceiver, anyhow::Error> {
+        let options = |port| MessageReceiverOptions {
+            bind_address: self.bind_address,
+            port,
             server_id: self.server_id.to_owned(),
-        });
-        
-        let mut _studio_process = KillOnDrop(
-            // Command::new(studio_install.application_path())
-            if cfg!(target_os = "linux") 
-            {
-                 //  run --command=vinegar org.vinegarhq.Vinegar run")
-                let mut cmd = Command::new("flatpak");
-
-
-                let a = cmd
-                    .arg("run")
-                    .arg("org.vinegarhq.Vinegar")
-                    .arg(format!("{}", self.place_path.display()))
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null());
-
-                a.spawn()?
-            } 
-            else 
-            {
-                Command::new( studio_install.application_path().as_os_str() )
-                    .arg(format!("{}", self.place_path.display()))
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .spawn()?
+            channel_capacity: self.channel_capacity,
+            scripts: self
+                .lua_scripts
+                .iter()
+                .map(|lua_script| plugin::wrap_script(lua_script))
+                .collect(),
+        };
+
+        let receiver = MessageReceiver::start(options(self.port));
+
+        match receiver {
+            Ok(receiver) => Ok(receiver),
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                let receiver = MessageReceiver::start(options(0))
+                    .context("Could not find a free port to listen on")?;
+
+                log::info!(
+                    "Port {} is already in use, using port {} instead",
+                    self.port,
+                    receiver.port()
+                );
+
+                Ok(receiver)
+            }
+            Err(err) => Err(err).with_context(|| {
+                format!(
+                    "Could not listen on port {}. Try choosing a different port with --port.",
+                    self.port
+                )
+            }),
+        }
+    }
+
+    /// Builds the command that launches Roblox Studio with our place.
+    fn studio_command(&self, studio_install: &RobloxStudio) -> Result<Command, anyhow::Error> {
+        let mut command = match &self.studio_path {
+            // Studio is a Windows application, so a Studio executable given to
+            // us on Linux needs to be started through wine.
+            Some(studio_path) if cfg!(target_os = "linux") => {
+                let mut command = Command::new("wine");
+                command.arg(studio_path);
+                command
+            }
+            Some(studio_path) if cfg!(target_os = "macos") => {
+                Command::new(studio::macos_executable(studio_path))
+            }
+            Some(studio_path) => Command::new(studio_path),
+            None if cfg!(target_os = "linux") => {
+                let mut command = Command::new("flatpak");
+                command.arg("run").arg("org.vinegarhq.Vinegar");
+                command
+            }
+            // The Roblox installer keeps every version of Studio side by side
+            // on Windows, so prefer the newest one it has installed.
+            None if cfg!(windows) => {
+                let installs = studio::windows_installs();
+
+                let install = match &self.studio_channel {
+                    Some(channel) => Some(
+                        installs
+                            .into_iter()
+                            .find(|install| install.matches_channel(channel))
+                            .ok_or_else(|| {
+                                anyhow!(
+                                    "Could not find a version of Roblox Studio from the '{}' \
+                                     channel. Use --list-studio to see the channel of the \
+                                     newest version, which is the only one that's recorded.",
+                                    channel
+                                )
+                            })?,
+                    ),
+                    None => installs.into_iter().next(),
+                };
+
+                match install {
+                    Some(install) => {
+                        log::debug!("Using Roblox Studio at {}", install.executable.display());
+                        Command::new(install.executable)
+                    }
+                    None => Command::new(studio_install.application_path()),
+                }
+            }
+            None if cfg!(target_os = "macos") => match studio::macos_install() {
+                Some(executable) => Command::new(executable),
+                None => Command::new(studio_install.application_path()),
             },
+            None => Command::new(studio_install.application_path()),
+        };
+
+        // xvfb-run gives Studio a virtual display to draw to on machines that
+        // don't have a real one.
+        if self.xvfb {
+            let mut xvfb_command = Command::new("xvfb-run");
+            xvfb_command
+                .arg("--auto-servernum")
+                .arg(command.get_program())
+                .args(command.get_args());
+            command = xvfb_command;
+        }
+
+        if let Some(wine_prefix) = &self.wine_prefix {
+            command.env("WINEPREFIX", wine_prefix);
+        }
+
+        command.envs(self.studio_env.iter().map(|(key, value)| (key, value)));
+
+        // When Studio is kept open, it needs to survive the user pressing
+        // Ctrl-C to stop run-in-roblox, so keep it out of our process group.
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+
+            if self.keep_open {
+                command.process_group(0);
+            }
+        }
+
+        command.arg(format!("{}", self.place_path.display()));
+        command.args(&self.studio_args);
+        Ok(command)
+    }
+
+    /// Writes our plugin into Studio's plugins folder, returning the path that
+    /// it was written to.
+    fn install_plugin(
+        &self,
+        studio_install: &RobloxStudio,
+        port: u16,
+    ) -> Result<PathBuf, anyhow::Error> {
+        if self.clean_plugins {
+            remove_stale_plugins(studio_install.plugins_path())?;
+        }
+
+        // Studio only creates its plugins folder once a plugin has been
+        // installed through it, so a fresh install might not have one yet.
+        let plugins_path = studio_install.plugins_path();
+        fs::create_dir_all(plugins_path).with_context(|| {
+            format!(
+                "Could not create Roblox Studio's plugins folder at {}",
+                plugins_path.display()
+            )
+        })?;
+
+        let plugin_file_path = plugins_path.join(format!("{}{}.rbxmx", PLUGIN_FILE_PREFIX, port));
+
+        let plugin_file = File::create(&plugin_file_path).with_context(|| {
+            format!(
+                "Could not write the run-in-roblox plugin into {}. \
+                 Check that the plugins folder is writable.",
+                plugins_path.display()
+            )
+        })?;
+        self.plugin(port).write(plugin_file)?;
+
+        verify_plugin(&plugin_file_path)?;
+
+        Ok(plugin_file_path)
+    }
+
+    fn plugin(&self, port: u16) -> RunInRbxPlugin<'_> {
+        RunInRbxPlugin {
+            template: self.plugin_template.as_deref(),
+            bind_address: self.bind_address,
+            port,
+            server_id: &self.server_id,
+            script_count: self.lua_scripts.len(),
+            args: &self.args,
+            fail_fast: self.fail_fast,
+            repeat: self.repeat,
+            keep_open: self.keep_open,
+            watch: self.script_updates.is_some(),
+            compress: self.compress,
+            context: self.context,
+            project: self.project.as_ref(),
+        }
+    }
+
+    /// Renders the plugin that would be installed for this run, without
+    /// installing it.
+    pub fn render_plugin(&self) -> Result<String, anyhow::Error> {
+        let mut output = Vec::new();
+        self.plugin(self.port).write(&mut output)?;
+
+        Ok(String::from_utf8(output)?)
+    }
+
+    /// Renders the source of the plugin's main script for this run, which is
+    /// the Luau that would be injected into Studio.
+    pub fn render_plugin_source(&self) -> String {
+        self.plugin(self.port).source()
+    }
+
+    /// Describes the command that would be used to launch Studio, without
+    /// launching it.
+    pub fn describe_studio_command(&self) -> Result<String, anyhow::Error> {
+        let studio_install =
+            RobloxStudio::locate().context("Could not locate a Roblox Studio installation.")?;
+
+        Ok(format!("{:?}", self.studio_command(&studio_install)?))
+    }
+
+    /// Installs the plugin without launching Studio, which is useful for
+    /// checking that run-in-roblox can find and write to the plugins folder.
+    pub fn install_plugin_only(&self) -> Result<PathBuf, anyhow::Error> {
+        let studio_install =
+            RobloxStudio::locate().context("Could not locate a Roblox Studio installation.")?;
+
+        self.install_plugin(&studio_install, self.port)
+    }
+
+    /// Launches Studio without our plugin and closes it again once it has had
+    /// time to start up. The first launch in a fresh wine prefix is much slower
+    /// than the ones after it, so this gets that out of the way.
+    fn warm_up(&self, studio_install: &RobloxStudio) -> Result<(), anyhow::Error> {
+        log::info!("Warming up Roblox Studio...");
+
+        let mut studio_process = KillOnDrop(
+            self.studio_command(studio_install)?
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()?,
+        );
+
+        let studio_session = self.studio_session(self.kill_signal);
+        let phase_start = Instant::now();
+
+        while phase_start.elapsed() < WARMUP_DURATION {
+            if self.cancel_token.is_cancelled() {
+                bail!("The run was cancelled");
+            }
+
+            // The process we spawned exiting doesn't mean that Studio did,
+            // since it might have handed off to another process.
+            if !studio::session_is_running(&studio_session) {
+                log::debug!("Roblox Studio exited during warmup");
+                break;
+            }
+
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        self.close_studio(&mut studio_process, self.kill_signal)
+    }
+
+    /// Launches Studio and waits for the plugin to connect, returning the
+    /// Studio process once it has.
+    fn launch_studio(
+        &self,
+        studio_install: &RobloxStudio,
+        message_receiver: &MessageReceiver,
+        timings: &mut RunTimings,
+    ) -> Result<KillOnDrop, anyhow::Error> {
+        // Studio's own output is only interesting when debugging a launch, so
+        // we only bother capturing it when it'll actually be logged.
+        let capture_output = log::log_enabled!(log::Level::Debug);
+        let studio_stdio = || {
+            if capture_output {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            }
+        };
+
+        let phase_start = Instant::now();
+        let mut studio_process = KillOnDrop(
+            self.studio_command(studio_install)?
+                .stdout(studio_stdio())
+                .stderr(studio_stdio())
+                .spawn()?,
         );
+        timings.studio_launch = phase_start.elapsed();
+
+        if let Some(stdout) = studio_process.0.stdout.take() {
+            forward_studio_output("stdout", stdout);
+        }
+
+        if let Some(stderr) = studio_process.0.stderr.take() {
+            forward_studio_output("stderr", stderr);
+        }
+
+        let studio_session = self.studio_session(self.kill_signal);
+        let phase_start = Instant::now();
+        let mut next_log = CONNECT_LOG_INTERVAL;
 
-        let first_message = message_receiver
-            .recv_timeout(Duration::from_secs(60))
-            .ok_or_else(|| {
-                anyhow!("Timeout reached while waiting for Roblox Studio to come online")
-            })?;
+        // Opening a big place can take a while, especially under wine, so let
+        // the user know that we're still waiting rather than sitting silently.
+        let first_message = loop {
+            let elapsed = phase_start.elapsed();
+
+            if elapsed >= self.connect_timeout {
+                bail!(
+                    "The run-in-roblox plugin never connected after {} seconds. \
+                     Check that Roblox Studio opened and that the plugin was installed, \
+                     or allow more time with --plugin-timeout",
+                    self.connect_timeout.as_secs()
+                );
+            }
+
+            // This blocks on the channel instead of polling it, so waiting on
+            // a slow launch costs no CPU. We only wake up to check on Studio.
+            let wait = (self.connect_timeout - elapsed).min(Duration::from_secs(1));
+
+            if let Some(message) = message_receiver.recv_timeout(wait) {
+                break message;
+            }
+
+            if self.cancel_token.is_cancelled() {
+                bail!("The run was cancelled");
+            }
+
+            // The launcher that we spawned exiting is normal, since it hands
+            // off to Studio, so only give up once Studio itself is gone.
+            if !studio::session_is_running(&studio_session) {
+                bail!("Roblox Studio exited before the run-in-roblox plugin connected");
+            }
+
+            let elapsed = phase_start.elapsed();
+
+            if elapsed >= next_log && elapsed < self.connect_timeout {
+                log::warn!(
+                    "Still waiting for Roblox Studio to open the place ({}s so far)...",
+                    elapsed.as_secs()
+                );
+                next_log += CONNECT_LOG_INTERVAL;
+            }
+        };
 
         match first_message {
             Message::Start => {}
+            Message::ProtocolMismatch { plugin_version } => bail!(
+                "Plugin/host version mismatch: the plugin speaks protocol version {} but \
+                 this copy of run-in-roblox speaks version {}. Try removing old run-in-roblox \
+                 plugins with --clean-plugins.",
+                plugin_version.map_or_else(|| "unknown".to_owned(), |version| version.to_string()),
+                plugin::PROTOCOL_VERSION
+            ),
             _ => bail!("Invalid first message received from Roblox Studio plugin"),
         }
 
+        timings.plugin_connect = phase_start.elapsed();
+
+        Ok(studio_process)
+    }
+
+    pub fn run(
+        &self,
+        sender: mpsc::SyncSender<Option<RobloxMessage>>,
+    ) -> Result<RunTimings, anyhow::Error> {
+        let mut timings = RunTimings::default();
+
+        // Without a display, Studio fails to start under wine in ways that
+        // are hard to make sense of.
+        if cfg!(target_os = "linux")
+            && !self.xvfb
+            && env::var_os("DISPLAY").is_none()
+            && env::var_os("WAYLAND_DISPLAY").is_none()
+        {
+            bail!(
+                "No display was found, and Roblox Studio needs one to start. On a headless \
+                 machine, pass --xvfb or run run-in-roblox under xvfb-run."
+            );
+        }
+
+        let studio_install =
+            RobloxStudio::locate().context("Could not locate a Roblox Studio installation.")?;
+
+        // This happens before the plugin is installed so that the warmup
+        // launch can't run any scripts.
+        if self.warmup {
+            let phase_start = Instant::now();
+            self.warm_up(&studio_install)?;
+            timings.warmup = phase_start.elapsed();
+        }
+
+        let phase_start = Instant::now();
+        let message_receiver = self.start_message_receiver()?;
+        let port = message_receiver.port();
+
+        // With several runs going at once, this is how to tell which Studio
+        // belongs to which run.
+        log::info!(
+            "Starting session {} on {}:{}",
+            self.server_id,
+            self.bind_address,
+            port
+        );
+
+        let plugin_file_path = self.install_plugin(&studio_install, port)?;
+        timings.plugin_install = phase_start.elapsed();
+
+        let mut attempt = 0;
+        let mut studio_process = loop {
+            match self.launch_studio(&studio_install, &message_receiver, &mut timings) {
+                Ok(studio_process) => break studio_process,
+                Err(err) if attempt < self.launch_retries && !self.cancel_token.is_cancelled() => {
+                    // The failed attempt's Studio might still be open without
+                    // the launcher that we spawned, and it would get in the
+                    // way of the next attempt.
+                    studio::close_studio(&self.studio_session(KillSignal::Kill));
+
+                    attempt += 1;
+                    log::warn!(
+                        "{:#}. Retrying launch ({} of {})...",
+                        err,
+                        attempt,
+                        self.launch_retries
+                    );
+
+                    thread::sleep(LAUNCH_RETRY_DELAY * attempt);
+                }
+                Err(err) => {
+                    // A crashed attempt would otherwise leave the plugin behind
+                    // to interfere with the next run.
+                    let _ignored = fs::remove_file(&plugin_file_path);
+                    return Err(err);
+                }
+            }
+        };
+
+        // Studio has already loaded the plugin by now. If we're leaving Studio
+        // open, we might never get another chance to clean it up.
+        if self.keep_open {
+            fs::remove_file(&plugin_file_path)?;
+        }
+
+        let phase_start = Instant::now();
+        let result = self.forward_messages(&message_receiver, &mut studio_process, &sender);
+        timings.script_run = phase_start.elapsed();
+
+        // This happens even if forwarding failed, which is usually because
+        // whoever was receiving messages from us has stopped listening, so that
+        // the plugin isn't left behind.
+        message_receiver.stop();
+
+        if !self.keep_open {
+            fs::remove_file(&plugin_file_path)?;
+        }
+
+        result?;
+        Ok(timings)
+    }
+
+    /// Passes messages from the plugin along to `sender` until the plugin says
+    /// that it's done, Studio exits, or the run is cancelled.
+    fn forward_messages(
+        &self,
+        message_receiver: &MessageReceiver,
+        studio_process: &mut KillOnDrop,
+        sender: &mpsc::SyncSender<Option<RobloxMessage>>,
+    ) -> Result<(), anyhow::Error> {
+        let studio_session = self.studio_session(self.kill_signal);
+
         loop {
-            match message_receiver.recv() {
-                Message::Start => {}
-                Message::Stop => {
+            // Being cancelled ends the run the same way as the plugin saying
+            // that it's done, except that Studio is closed whether or not it
+            // was meant to stay open.
+            if self.cancel_token.is_cancelled() {
+                log::info!("Run cancelled, closing Roblox Studio");
+                self.close_studio(studio_process, self.kill_signal)?;
+                sender.send(None)?;
+                break;
+            }
+
+            if let Some(script_updates) = &self.script_updates {
+                // Only the newest version of the scripts matters.
+                if let Some(lua_scripts) = script_updates.try_iter().last() {
+                    message_receiver.set_scripts(
+                        lua_scripts
+                            .iter()
+                            .map(|lua_script| plugin::wrap_script(lua_script))
+                            .collect(),
+                    );
+                }
+            }
+
+            match message_receiver.recv_timeout(Duration::from_millis(500)) {
+                Some(Message::Start) | Some(Message::ProtocolMismatch { .. }) => {}
+                Some(Message::Stop) => {
                     sender.send(None)?;
                     break;
                 }
-                Message::Messages(roblox_messages) => {
+                Some(Message::Messages(roblox_messages)) => {
                     for message in roblox_messages.into_iter() {
                         sender.send(Some(message))?;
                     }
                 }
+                None => {
+                    // If Studio goes away before the plugin tells us that it's
+                    // done, it must have crashed. The process we spawned is
+                    // no help here, since it might have handed off to
+                    // another one and exited.
+                    if !studio::session_is_running(&studio_session) {
+                        // ...unless we're keeping it open or watching, in
+                        // which case the user closing Studio is how the
+                        // session ends.
+                        if self.keep_open || self.script_updates.is_some() {
+                            sender.send(None)?;
+                        } else {
+                            sender.send(Some(RobloxMessage::StudioExited))?;
+                        }
+
+                        break;
+                    }
+                }
             }
         }
 
-        message_receiver.stop();
-        fs::remove_file(&plugin_file_path)?;
-
         Ok(())
     }
 }
+
+/// Checks that the plugin we just wrote made it to disk in one piece and can
+/// be read back, so that a failed install is reported now instead of as a
+/// plugin that never connects.
+fn verify_plugin(plugin_file_path: &Path) -> Result<(), anyhow::Error> {
+    let mut contents = Vec::new();
+    File::open(plugin_file_path)
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .with_context(|| {
+            format!(
+                "The run-in-roblox plugin was installed to {} but could not be read back",
+                plugin_file_path.display()
+            )
+        })?;
+
+    if contents.is_empty() {
+        bail!(
+            "The run-in-roblox plugin installed to {} is empty. \
+             Check that there is space left on the disk.",
+            plugin_file_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes any plugins that earlier runs installed but never cleaned up, like
+/// when run-in-roblox was killed before Studio exited.
+fn remove_stale_plugins(plugins_path: &Path) -> Result<(), anyhow::Error> {
+    let entries = match fs::read_dir(plugins_path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        let is_stale_plugin = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| {
+                name.starts_with(PLUGIN_FILE_PREFIX) && name.ends_with(".rbxmx")
+            });
+
+        if is_stale_plugin {
+            log::info!("Removing stale plugin {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a random, unique ID for a session. The plugin we inject compares
+/// this value with the one reported by the server and aborts if they don't
+/// match. It's also used to name our temporary place, which lets us tell our
+/// Studio process apart from any others when cleaning up.
+pub fn generate_server_id() -> String {
+    format!("run-in-roblox-{:x}", rand::random::<u128>())
+}
+
+/// Builds a `PlaceRunner`, filling in defaults for anything that isn't set.
+pub struct PlaceRunnerBuilder {
+    runner: PlaceRunner,
+}
+
+impl PlaceRunnerBuilder {
+    /// The place file to open. Studio may modify or lock this file, so it's
+    /// best to pass a copy.
+    pub fn place_path(mut self, place_path: impl Into<PathBuf>) -> Self {
+        self.runner.place_path = place_path.into();
+        self
+    }
+
+    /// The address to listen for the plugin on. Defaults to `127.0.0.1`.
+    pub fn bind_address(mut self, bind_address: IpAddr) -> Self {
+        self.runner.bind_address = bind_address;
+        self
+    }
+
+    /// The port to listen for the plugin on. Defaults to any free port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.runner.port = port;
+        self
+    }
+
+    /// Overrides the generated server ID.
+    pub fn server_id(mut self, server_id: impl Into<String>) -> Self {
+        self.runner.server_id = server_id.into();
+        self
+    }
+
+    /// Adds a script to run after any scripts that were already added.
+    pub fn script(mut self, lua_script: impl Into<String>) -> Self {
+        self.runner.lua_scripts.push(lua_script.into());
+        self
+    }
+
+    /// Adds an argument to pass to each script.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.runner.args.push(arg.into());
+        self
+    }
+
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.runner.fail_fast = fail_fast;
+        self
+    }
+
+    pub fn repeat(mut self, repeat: u32) -> Self {
+        self.runner.repeat = repeat;
+        self
+    }
+
+    pub fn studio_path(mut self, studio_path: impl Into<PathBuf>) -> Self {
+        self.runner.studio_path = Some(studio_path.into());
+        self
+    }
+
+    pub fn studio_channel(mut self, studio_channel: impl Into<String>) -> Self {
+        self.runner.studio_channel = Some(studio_channel.into());
+        self
+    }
+
+    /// Adds an extra argument to launch Studio with.
+    pub fn studio_arg(mut self, studio_arg: impl Into<String>) -> Self {
+        self.runner.studio_args.push(studio_arg.into());
+        self
+    }
+
+    /// Adds an environment variable to launch Studio with.
+    pub fn studio_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.runner.studio_env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn wine_prefix(mut self, wine_prefix: impl Into<PathBuf>) -> Self {
+        self.runner.wine_prefix = Some(wine_prefix.into());
+        self
+    }
+
+    pub fn keep_open(mut self, keep_open: bool) -> Self {
+        self.runner.keep_open = keep_open;
+        self
+    }
+
+    pub fn context(mut self, context: ScriptContext) -> Self {
+        self.runner.context = context;
+        self
+    }
+
+    pub fn project(mut self, project: ProjectNode) -> Self {
+        self.runner.project = Some(project);
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.runner.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn launch_retries(mut self, launch_retries: u32) -> Self {
+        self.runner.launch_retries = launch_retries;
+        self
+    }
+
+    pub fn clean_plugins(mut self, clean_plugins: bool) -> Self {
+        self.runner.clean_plugins = clean_plugins;
+        self
+    }
+
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.runner.compress = compress;
+        self
+    }
+
+    pub fn xvfb(mut self, xvfb: bool) -> Self {
+        self.runner.xvfb = xvfb;
+        self
+    }
+
+    pub fn warmup(mut self, warmup: bool) -> Self {
+        self.runner.warmup = warmup;
+        self
+    }
+
+    pub fn kill_signal(mut self, kill_signal: KillSignal) -> Self {
+        self.runner.kill_signal = kill_signal;
+        self
+    }
+
+    pub fn studio_executable_name(mut self, studio_executable_name: impl Into<String>) -> Self {
+        self.runner
+            .studio_executable_names
+            .push(studio_executable_name.into());
+        self
+    }
+
+    /// Cancels the run when this token is cancelled, for sharing one token
+    /// between several runners.
+    pub fn cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.runner.cancel_token = cancel_token;
+        self
+    }
+
+    /// Installs a plugin with this source instead of our own.
+    pub fn plugin_template(mut self, plugin_template: impl Into<String>) -> Self {
+        self.runner.plugin_template = Some(plugin_template.into());
+        self
+    }
+
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.runner.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// Keeps the plugin running after the scripts finish, and runs each new
+    /// version of the scripts sent on `script_updates`.
+    pub fn script_updates(mut self, script_updates: mpsc::Receiver<Vec<String>>) -> Self {
+        self.runner.script_updates = Some(script_updates);
+        self
+    }
+
+    pub fn build(self) -> Result<PlaceRunner, anyhow::Error> {
+        if self.runner.place_path.as_os_str().is_empty() {
+            bail!("A place path must be given to run scripts in");
+        }
+
+        Ok(self.runner)
+    }
+
+    /// Builds the runner and starts it on a background thread. Messages from
+    /// Studio arrive on the returned receiver, which yields `None` once the
+    /// plugin is done. The thread's result says how the run went.
+    pub fn run(
+        self,
+    ) -> Result<
+        (
+            mpsc::Receiver<Option<RobloxMessage>>,
+            thread::JoinHandle<Result<RunTimings, anyhow::Error>>,
+        ),
+        anyhow::Error,
+    > {
+        let runner = self.build()?;
+        let (sender, receiver) = mpsc::sync_channel(runner.channel_capacity);
+        let handle = thread::spawn(move || runner.run(sender));
+
+        Ok((receiver, handle))
+    }
+}
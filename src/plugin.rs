@@ -1,15 +1,112 @@
-use std::{collections::HashMap, io::Write};
+use std::{collections::HashMap, io::Write, net::IpAddr, str::FromStr};
 
 use rbx_xml::EncodeError;
 
 use rbx_dom_weak::{RbxInstanceProperties, RbxTree, RbxValue};
+use serde_json::json;
+
+use crate::project::ProjectNode;
 
 static PLUGIN_TEMPLATE: &'static str = include_str!("plugin_main_template.lua");
 
+/// The placeholders that a plugin has to fill in to be able to find and talk
+/// to us, and to check that it's talking to the right version of us.
+static REQUIRED_PLACEHOLDERS: &[&str] = &["{{PORT}}", "{{SERVER_ID}}", "{{PROTOCOL_VERSION}}"];
+
+/// The version of the protocol that the plugin uses to talk to us. This should
+/// be bumped whenever messages change in a way that an older plugin wouldn't
+/// understand.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The functions from the plugin's `api` table that are made available to each
+/// script as locals.
+static SCRIPT_API: &[&str] = &[
+    "exit",
+    "abort",
+    "reportTestResults",
+    "reportProgress",
+    "writeFile",
+];
+
+/// Where and how the plugin runs scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptContext {
+    /// Run scripts inside the plugin while the place is being edited. This
+    /// gives them plugin-level security.
+    Plugin,
+
+    /// The same as `Plugin`.
+    Edit,
+
+    /// Start running the game, then run scripts inside the plugin.
+    Run,
+
+    /// Start running the game, then run scripts as server Scripts with the
+    /// same security as the game's own scripts.
+    Server,
+}
+
+impl ScriptContext {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScriptContext::Plugin => "plugin",
+            ScriptContext::Edit => "edit",
+            ScriptContext::Run => "run",
+            ScriptContext::Server => "server",
+        }
+    }
+}
+
+impl FromStr for ScriptContext {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "plugin" => Ok(ScriptContext::Plugin),
+            "edit" => Ok(ScriptContext::Edit),
+            "run" => Ok(ScriptContext::Run),
+            "server" => Ok(ScriptContext::Server),
+            _ => Err(format!("Unknown script context '{}'", source)),
+        }
+    }
+}
+
+/// Checks that a custom plugin given with `--plugin-path` gets enough from us
+/// to connect, since a plugin that doesn't would leave us waiting until
+/// `--plugin-timeout` with no hint as to why.
+pub fn check_template(template: &str) -> Result<(), anyhow::Error> {
+    let missing: Vec<_> = REQUIRED_PLACEHOLDERS
+        .iter()
+        .filter(|placeholder| !template.contains(*placeholder))
+        .copied()
+        .collect();
+
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Custom plugin is missing placeholders that it needs to connect: {}",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
 pub struct RunInRbxPlugin<'a> {
+    /// The source of the plugin with placeholders still in it. Defaults to our
+    /// own plugin.
+    pub template: Option<&'a str>,
+    pub bind_address: IpAddr,
     pub port: u16,
     pub server_id: &'a str,
-    pub lua_script: &'a str,
+    pub script_count: usize,
+    pub args: &'a [String],
+    pub fail_fast: bool,
+    pub repeat: u32,
+    pub keep_open: bool,
+    pub watch: bool,
+    pub compress: bool,
+    pub context: ScriptContext,
+    pub project: Option<&'a ProjectNode>,
 }
 
 impl<'a> RunInRbxPlugin<'a> {
@@ -20,11 +117,33 @@ impl<'a> RunInRbxPlugin<'a> {
         rbx_xml::to_writer_default(output, &tree, &[root_id])
     }
 
-    fn build_plugin(&self) -> RbxTree {
-        let complete_source = PLUGIN_TEMPLATE
+    /// The source of the plugin's main script, with the details of this run
+    /// filled in.
+    pub fn source(&self) -> String {
+        // A server listening on every interface can be reached on localhost,
+        // but one listening on a specific address can only be reached there.
+        let host = match self.bind_address {
+            address if address.is_unspecified() => "localhost".to_owned(),
+            IpAddr::V4(address) => address.to_string(),
+            IpAddr::V6(address) => format!("[{}]", address),
+        };
+
+        self.template
+            .unwrap_or(PLUGIN_TEMPLATE)
+            .replace("{{HOST}}", &host)
             .replace("{{PORT}}", &self.port.to_string())
-            .replace("{{SERVER_ID}}", self.server_id);
+            .replace("{{PROTOCOL_VERSION}}", &PROTOCOL_VERSION.to_string())
+            .replace("{{SERVER_ID}}", self.server_id)
+            .replace("{{FAIL_FAST}}", &self.fail_fast.to_string())
+            .replace("{{REPEAT}}", &self.repeat.to_string())
+            .replace("{{SCRIPT_COUNT}}", &self.script_count.to_string())
+            .replace("{{KEEP_OPEN}}", &self.keep_open.to_string())
+            .replace("{{WATCH}}", &self.watch.to_string())
+            .replace("{{COMPRESS}}", &self.compress.to_string())
+            .replace("{{CONTEXT}}", self.context.as_str())
+    }
 
+    fn build_plugin(&self) -> RbxTree {
         let plugin_script = RbxInstanceProperties {
             name: "run-in-roblox-plugin".to_owned(),
             class_name: "Script".to_owned(),
@@ -34,7 +153,7 @@ impl<'a> RunInRbxPlugin<'a> {
                 properties.insert(
                     "Source".to_owned(),
                     RbxValue::String {
-                        value: complete_source,
+                        value: self.source(),
                     },
                 );
 
@@ -42,24 +161,72 @@ impl<'a> RunInRbxPlugin<'a> {
             },
         };
 
-        let main_source = format!("return function()\n{}\nend", self.lua_script);
-
-        let injected_main = RbxInstanceProperties {
-            name: "Main".to_owned(),
-            class_name: "ModuleScript".to_owned(),
+        // Arguments are stored as JSON in a StringValue instead of being
+        // substituted into the plugin source so that we don't need to worry
+        // about escaping them.
+        let injected_args = RbxInstanceProperties {
+            name: "Args".to_owned(),
+            class_name: "StringValue".to_owned(),
             properties: {
                 let mut properties = HashMap::new();
 
-                properties.insert("Source".to_owned(), RbxValue::String { value: main_source });
+                properties.insert(
+                    "Value".to_owned(),
+                    RbxValue::String {
+                        value: json!(self.args).to_string(),
+                    },
+                );
 
                 properties
             },
         };
 
+        let scripts_folder = RbxInstanceProperties {
+            name: "Scripts".to_owned(),
+            class_name: "Folder".to_owned(),
+            properties: HashMap::new(),
+        };
+
         let mut tree = RbxTree::new(plugin_script);
         let root_id = tree.get_root_id();
-        tree.insert_instance(injected_main, root_id);
+        tree.insert_instance(injected_args, root_id);
+
+        // The plugin fills this folder with scripts that it fetches from us.
+        tree.insert_instance(scripts_folder, root_id);
+
+        // The plugin moves the project into ReplicatedStorage before running
+        // any scripts so that they can require it.
+        if let Some(project) = self.project {
+            let project_folder = RbxInstanceProperties {
+                name: "Project".to_owned(),
+                class_name: "Folder".to_owned(),
+                properties: HashMap::new(),
+            };
+
+            let project_folder_id = tree.insert_instance(project_folder, root_id);
+            project.insert_into(&mut tree, project_folder_id);
+        }
 
         tree
     }
 }
+
+/// Wraps a script so that when the plugin requires it, it gets back a function
+/// that runs the script.
+///
+/// Each script is given the plugin's API as locals. Arguments from the command
+/// line are passed in as varargs, just like a regular Lua chunk. Everything
+/// before the script stays on one line so that line numbers in errors still
+/// match the user's file.
+pub fn wrap_script(lua_script: &str) -> String {
+    format!(
+        "return function(runInRoblox, ...) local {} = {} {}\nend",
+        SCRIPT_API.join(", "),
+        SCRIPT_API
+            .iter()
+            .map(|name| format!("runInRoblox.{}", name))
+            .collect::<Vec<_>>()
+            .join(", "),
+        lua_script
+    )
+}
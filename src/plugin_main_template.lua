@@ -1,15 +1,36 @@
+local HOST = "{{HOST}}"
 local PORT = "{{PORT}}"
+local PROTOCOL_VERSION = {{PROTOCOL_VERSION}}
 local SERVER_ID = "{{SERVER_ID}}"
+local FAIL_FAST = {{FAIL_FAST}}
+local REPEAT = {{REPEAT}}
+local SCRIPT_COUNT = {{SCRIPT_COUNT}}
+local CONTEXT = "{{CONTEXT}}"
+local KEEP_OPEN = {{KEEP_OPEN}}
+local WATCH = {{WATCH}}
+local COMPRESS = {{COMPRESS}}
 
-local SERVER_URL = string.format("http://localhost:%s", PORT)
+local SERVER_URL = string.format("http://%s:%s", HOST, PORT)
 
 local HttpService = game:GetService("HttpService")
 local LogService = game:GetService("LogService")
 local RunService = game:GetService("RunService")
 
-local pingSuccess, remoteServerId = pcall(function()
-	return HttpService:GetAsync(SERVER_URL)
-end)
+-- Every request includes our server ID so that the server knows the request
+-- came from us.
+local function post(path, body, compress)
+	return HttpService:PostAsync(SERVER_URL .. path, body, Enum.HttpContentType.ApplicationJson, compress or false, {
+		["X-Run-In-Roblox-Server-Id"] = SERVER_ID,
+	})
+end
+
+local function get(path)
+	return HttpService:GetAsync(SERVER_URL .. path, true, {
+		["X-Run-In-Roblox-Server-Id"] = SERVER_ID,
+	})
+end
+
+local pingSuccess, remoteServerId = pcall(get, "/")
 
 -- If there was a transport error, just abort silently.
 --
@@ -26,59 +47,233 @@ if remoteServerId ~= SERVER_ID then
 end
 
 local queuedMessages = {}
-local timeSinceLastSend = 0
-local messageSendRate = 0.1
+local isFlushing = false
 
+-- Sends all queued messages to the server. Only one flush happens at a time so
+-- that messages arrive in the order they were logged; messages queued while a
+-- flush is in progress are picked up by the same flush.
 local function flushMessages()
-	if #queuedMessages == 0 then
+	if isFlushing then
 		return
 	end
 
-	local encoded = HttpService:JSONEncode(queuedMessages)
-	queuedMessages = {}
+	isFlushing = true
+
+	-- If a request fails, the flush has to end anyway, or no flush would ever
+	-- start again. The failure isn't logged, since that would queue another
+	-- message for a server that we can't reach.
+	pcall(function()
+		while #queuedMessages > 0 do
+			local encoded = HttpService:JSONEncode(queuedMessages)
+			queuedMessages = {}
+
+			post("/messages", encoded, COMPRESS)
+		end
+	end)
+
+	isFlushing = false
+end
 
-	timeSinceLastSend = 0
-	HttpService:PostAsync(SERVER_URL .. "/messages", encoded)
+local function queueMessage(message)
+	table.insert(queuedMessages, message)
+	task.spawn(flushMessages)
 end
 
-local heartbeatConnection = RunService.Heartbeat:Connect(function(dt)
-	timeSinceLastSend = timeSinceLastSend + dt
+-- While the run is going, let the server know every so often that Studio is
+-- still alive, even if the script isn't printing anything.
+local heartbeatInterval = 1
+local isRunning = true
 
-	if timeSinceLastSend >= messageSendRate then
-		flushMessages()
+task.spawn(function()
+	while isRunning do
+		queueMessage({
+			type = "Heartbeat",
+		})
+
+		task.wait(heartbeatInterval)
 	end
 end)
 
-local logTypeToLevel = {
-	[Enum.MessageType.MessageOutput] = "Print",
-	[Enum.MessageType.MessageInfo] = "Info",
-	[Enum.MessageType.MessageWarning] = "Warning",
-	[Enum.MessageType.MessageError] = "Error",
-}
+-- Output is timestamped relative to when the plugin started, so that delays
+-- in Studio can be told apart from delays in sending output to the server.
+local startClock = os.clock()
+
+-- Tells the server which part of the session output came from, so that it
+-- can keep output from the server and clients apart.
+local function outputContext()
+	if not RunService:IsRunning() then
+		return "edit"
+	elseif RunService:IsClient() and not RunService:IsServer() then
+		return "client"
+	else
+		return "server"
+	end
+end
 
 local logConnection = LogService.MessageOut:Connect(function(body, messageType)
-	table.insert(queuedMessages, {
+	-- LogService doesn't tell us where a message came from, but errors and
+	-- warnings raised by scripts are prefixed with the script's name and line.
+	-- Prints aren't, so a print that happens to look like that is left alone.
+	local source, line
+
+	if messageType == Enum.MessageType.MessageWarning or messageType == Enum.MessageType.MessageError then
+		local rest
+		source, line, rest = string.match(body, "^([^:\n]+):(%d+): (.*)$")
+
+		if source ~= nil then
+			body = rest
+		end
+	end
+
+	queueMessage({
 		type = "Output",
-		level = logTypeToLevel[messageType] or "Info",
+		-- The server decides what level each message type is, so that the
+		-- mapping lives in one place.
+		level = messageType.Value,
 		body = body,
+		source = source,
+		line = tonumber(line),
+		studio_time = os.clock() - startClock,
+		context = outputContext(),
 	})
 end)
 
-HttpService:PostAsync(SERVER_URL .. "/start", "")
+-- Scripts can call exit(code) to end early and choose the exit code that
+-- run-in-roblox reports. We unwind the script by throwing this sentinel value.
+local exitSentinel = {}
 
-local loadSuccess, messageOrMain = xpcall(require, debug.traceback, script.Main)
+-- run-in-roblox only understands whole exit codes, so anything else is a
+-- mistake in the script that it should hear about right away, rather than the
+-- run ending some other way because the server couldn't read the code.
+local function checkExitCode(code)
+	if code == nil then
+		return 0
+	end
 
-if not loadSuccess then
-	local sacrificialEvent = Instance.new("BindableEvent")
-	sacrificialEvent.Event:Connect(function()
-		error(messageOrMain, 0)
-	end)
-	sacrificialEvent:Fire()
+	if type(code) ~= "number" or code % 1 ~= 0 or code < -2147483648 or code > 2147483647 then
+		error(string.format("exit code must be a whole number, got %s", tostring(code)), 3)
+	end
+
+	return code
+end
+
+local function exit(code)
+	queueMessage({
+		type = "Exit",
+		code = checkExitCode(code),
+	})
+
+	error(exitSentinel, 0)
 end
 
-local mainSuccess, message = xpcall(messageOrMain, debug.traceback)
+-- Scripts can call abort(reason) when something is so wrong that there's no
+-- point in carrying on, like a missing precondition. run-in-roblox stops right
+-- away and closes Studio.
+local function reportAbort(reason)
+	queueMessage({
+		type = "Abort",
+		reason = tostring(reason),
+	})
+end
+
+local function abort(reason)
+	reportAbort(reason)
+	error(exitSentinel, 0)
+end
 
-if not mainSuccess then
+-- Module-style scripts are given done(code) instead, which reports the exit
+-- code without interrupting the script.
+local function done(code)
+	queueMessage({
+		type = "Exit",
+		code = checkExitCode(code),
+	})
+end
+
+-- Test frameworks can report their results with reportTestResults so that
+-- run-in-roblox can summarize them and fail the run if any tests failed.
+local function reportTestResults(results)
+	queueMessage({
+		type = "TestResults",
+		passed = results.passed or 0,
+		failed = results.failed or 0,
+		skipped = results.skipped or 0,
+		failures = results.failures or {},
+	})
+end
+
+-- Long-running scripts can report how far along they are with reportProgress,
+-- which run-in-roblox shows as a progress bar. run-in-roblox counts progress
+-- in whole steps, so fractions of a step are rounded down.
+local function reportProgress(current, total, label)
+	if type(current) ~= "number" or type(total) ~= "number" then
+		error("reportProgress expects numbers for current and total", 2)
+	end
+
+	if label ~= nil then
+		label = tostring(label)
+	end
+
+	queueMessage({
+		type = "Progress",
+		current = math.max(math.floor(current), 0),
+		total = math.max(math.floor(total), 0),
+		label = label,
+	})
+end
+
+-- Scripts can save files like coverage reports with writeFile. They end up in
+-- the folder given to run-in-roblox with --artifact-dir.
+local function writeFile(relativePath, contents)
+	queueMessage({
+		type = "FileWrite",
+		relative_path = relativePath,
+		contents = contents,
+	})
+end
+
+-- Everything that scripts can use to talk to run-in-roblox. Keep this in sync
+-- with SCRIPT_API in plugin.rs.
+local api = {
+	exit = exit,
+	abort = abort,
+	reportTestResults = reportTestResults,
+	reportProgress = reportProgress,
+	writeFile = writeFile,
+}
+
+-- Used as the handler for xpcall so that a script's errors are reported along
+-- with where they happened.
+local function captureError(message)
+	if message == exitSentinel then
+		return message
+	end
+
+	return {
+		message = tostring(message),
+		traceback = debug.traceback(),
+	}
+end
+
+-- The server answers with the protocol version that it speaks. If it isn't
+-- ours, it won't understand our messages, and it reports the mismatch itself.
+local hostVersion = tonumber(post("/start", HttpService:JSONEncode({
+	protocol_version = PROTOCOL_VERSION,
+})))
+
+if hostVersion ~= PROTOCOL_VERSION then
+	warn(string.format(
+		"run-in-roblox plugin/host version mismatch: the plugin speaks protocol version %d but the host speaks %s",
+		PROTOCOL_VERSION,
+		tostring(hostVersion)
+	))
+
+	isRunning = false
+	logConnection:Disconnect()
+	return
+end
+
+local function reportError(message)
 	local sacrificialEvent = Instance.new("BindableEvent")
 	sacrificialEvent.Event:Connect(function()
 		error(message, 0)
@@ -86,12 +281,289 @@ if not mainSuccess then
 	sacrificialEvent:Fire()
 end
 
--- Wait for any remaining messages to be sent to LogService, then flush them
--- explicitly.
-wait(2 * messageSendRate)
-heartbeatConnection:Disconnect()
+-- Reports an error caught by captureError, which run-in-roblox shows along
+-- with its traceback.
+local function reportScriptError(err)
+	queueMessage({
+		type = "ScriptError",
+		message = err.message,
+		traceback = err.traceback,
+	})
+end
+
+local args = HttpService:JSONDecode(script.Args.Value)
+
+-- Runs one of the injected scripts, returning whether it succeeded and whether
+-- it asked to end the run by calling exit().
+local function runScript(module)
+	local loadSuccess, messageOrMain = xpcall(require, captureError, module)
+
+	if not loadSuccess then
+		reportScriptError(messageOrMain)
+		return false, false
+	end
+
+	local mainSuccess, message = xpcall(messageOrMain, captureError, api, table.unpack(args))
+
+	-- Scripts written in the style of a module return a function instead,
+	-- which we call with some context about the run.
+	if mainSuccess and typeof(message) == "function" then
+		local context = {
+			serverId = SERVER_ID,
+			args = args,
+			done = done,
+			abort = abort,
+			reportTestResults = reportTestResults,
+			reportProgress = reportProgress,
+			writeFile = writeFile,
+		}
+
+		mainSuccess, message = xpcall(message, captureError, context)
+	end
+
+	if not mainSuccess then
+		if message == exitSentinel then
+			return true, true
+		end
+
+		reportScriptError(message)
+		return false, false
+	end
+
+	return true, false
+end
+
+-- The source of the Script that runs a user's script in the server context.
+-- Server scripts can't call into the plugin directly, so they talk to it
+-- through the Bridge BindableFunction that we give them.
+local SERVER_RUNNER_SOURCE = [==[
+local bridge = script.Bridge
+local exitSentinel = {}
+
+local runInRoblox = {
+	exit = function(code)
+		bridge:Invoke("done", code)
+		error(exitSentinel, 0)
+	end,
+	abort = function(reason)
+		bridge:Invoke("abort", reason)
+		error(exitSentinel, 0)
+	end,
+	reportTestResults = function(results)
+		bridge:Invoke("reportTestResults", results)
+	end,
+	reportProgress = function(current, total, label)
+		bridge:Invoke("reportProgress", current, total, label)
+	end,
+	writeFile = function(relativePath, contents)
+		bridge:Invoke("writeFile", relativePath, contents)
+	end,
+}
+
+local function captureError(message)
+	if message == exitSentinel then
+		return message
+	end
+
+	return {
+		message = tostring(message),
+		traceback = debug.traceback(),
+	}
+end
+
+local success, message = xpcall(function()
+	return require(script.Main)(runInRoblox, table.unpack(bridge:Invoke("args")))
+end, captureError)
+
+if success then
+	script.Finished:Fire(true, nil, false)
+elseif message == exitSentinel then
+	script.Finished:Fire(true, nil, true)
+else
+	script.Finished:Fire(false, message, false)
+end
+]==]
+
+-- Runs one of the injected scripts as a server Script instead of inside the
+-- plugin, returning the same results as runScript.
+local function runServerScript(module)
+	local runner = Instance.new("Script")
+	runner.Name = "run-in-roblox-" .. module.Name
+	runner.Source = SERVER_RUNNER_SOURCE
+
+	local main = module:Clone()
+	main.Name = "Main"
+	main.Parent = runner
+
+	local bridge = Instance.new("BindableFunction")
+	bridge.Name = "Bridge"
+	bridge.OnInvoke = function(name, ...)
+		if name == "args" then
+			return args
+		elseif name == "done" then
+			return done(...)
+		elseif name == "abort" then
+			-- The server script unwinds itself, so only report the abort.
+			return reportAbort(...)
+		elseif name == "reportTestResults" then
+			return reportTestResults(...)
+		elseif name == "reportProgress" then
+			return reportProgress(...)
+		elseif name == "writeFile" then
+			return writeFile(...)
+		end
+	end
+	bridge.Parent = runner
+
+	local finished = Instance.new("BindableEvent")
+	finished.Name = "Finished"
+	finished.Parent = runner
+
+	runner.Parent = game:GetService("ServerScriptService")
+	local success, message, exited = finished.Event:Wait()
+	runner:Destroy()
+
+	if not success then
+		reportScriptError(message)
+	end
+
+	return success, exited
+end
+
+-- Scripts in the run and server contexts need the game to be running.
+if CONTEXT == "run" or CONTEXT == "server" then
+	game:GetService("RunService"):Run()
+end
+
+-- Make the user's project available to scripts before running any of them.
+local project = script:FindFirstChild("Project")
+
+if project ~= nil then
+	for _, child in ipairs(project:GetChildren()) do
+		child.Parent = game:GetService("ReplicatedStorage")
+	end
+end
+
+local scripts = script.Scripts
+
+-- Scripts aren't baked into the plugin, which would make it huge for big
+-- scripts, so fetch them all before running any of them. In watch mode, this
+-- happens again each time the scripts change.
+local function fetchScripts()
+	scripts:ClearAllChildren()
+
+	for index = 1, SCRIPT_COUNT do
+		local fetchSuccess, sourceOrError = pcall(get, "/scripts/" .. index)
+
+		if not fetchSuccess then
+			reportError("Failed to fetch script " .. index .. ": " .. tostring(sourceOrError))
+			break
+		end
+
+		local module = Instance.new("ModuleScript")
+		module.Name = tostring(index)
+		module.Source = sourceOrError
+		module.Parent = scripts
+	end
+end
+
+-- Runs every script in order, returning whether to stop running entirely.
+local function runAllScripts()
+	for index = 1, #scripts:GetChildren() do
+		queueMessage({
+			type = "ScriptStart",
+			index = index,
+		})
+
+		local module = scripts:FindFirstChild(tostring(index))
+		local success, exited
+
+		if CONTEXT == "server" then
+			success, exited = runServerScript(module)
+		else
+			success, exited = runScript(module)
+		end
+
+		if exited or (not success and FAIL_FAST) then
+			return true
+		end
+	end
+
+	return false
+end
+
+local function runIterations()
+	for iteration = 1, REPEAT do
+		queueMessage({
+			type = "IterationStart",
+			iteration = iteration,
+		})
+
+		if runAllScripts() then
+			break
+		end
+	end
+end
+
+if not game:IsLoaded() then
+	game.Loaded:Wait()
+end
+
+-- Let the server know which place opened in which version of Studio, which is
+-- the first thing to check when the wrong place seems to have run.
+queueMessage({
+	type = "Ready",
+	studio_version = version(),
+	place_name = game.Name,
+})
+
+fetchScripts()
+runIterations()
+
+-- In watch mode, run the scripts again whenever the server has new versions
+-- of them, until the server goes away.
+if WATCH then
+	local revisionSuccess, revision = pcall(get, "/revision")
+
+	while revisionSuccess do
+		task.wait(0.5)
+
+		local latestSuccess, latest = pcall(get, "/revision")
+
+		if not latestSuccess then
+			break
+		end
+
+		if latest ~= revision then
+			revision = latest
+
+			queueMessage({
+				type = "ScriptsChanged",
+			})
+
+			fetchScripts()
+			runIterations()
+		end
+	end
+
+	return
+end
+
+-- When Studio is being kept open, keep forwarding output for as long as the
+-- server is around to hear it.
+if KEEP_OPEN then
+	return
+end
+
+-- Wait for any remaining messages to be sent to LogService, then make sure
+-- they've all reached the server before we say that we're done.
+task.wait(0.2)
+isRunning = false
 logConnection:Disconnect()
 
-flushMessages()
+repeat
+	flushMessages()
+	task.wait()
+until not isFlushing and #queuedMessages == 0
 
-HttpService:PostAsync(SERVER_URL .. "/stop", "")
\ No newline at end of file
+post("/stop", "")
\ No newline at end of file
@@ -0,0 +1,117 @@
+use std::{collections::HashMap, io, path::Path};
+
+use fs_err as fs;
+use rbx_dom_weak::{RbxId, RbxInstanceProperties, RbxTree, RbxValue};
+
+/// An instance read from a directory on disk, following the same file naming
+/// conventions that Rojo uses:
+///
+/// * Directories become `Folder` instances.
+/// * `foo.lua` becomes a `ModuleScript`, `foo.server.lua` a `Script`, and
+///   `foo.client.lua` a `LocalScript`. `.luau` works the same way.
+/// * A directory containing an `init.lua` (or `init.server.lua`, etc.) becomes
+///   that script instead of a folder, with the rest of the directory as its
+///   children.
+///
+/// Anything else in the directory is ignored.
+#[derive(Debug)]
+pub struct ProjectNode {
+    pub name: String,
+    pub class_name: &'static str,
+    pub source: Option<String>,
+    pub children: Vec<ProjectNode>,
+}
+
+impl ProjectNode {
+    /// Reads a directory into a tree of instances.
+    pub fn read(path: &Path) -> io::Result<ProjectNode> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Project".to_owned());
+
+        let mut node = ProjectNode {
+            name,
+            class_name: "Folder",
+            source: None,
+            children: Vec::new(),
+        };
+
+        let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let entry_path = entry.path();
+
+            if entry.file_type()?.is_dir() {
+                node.children.push(ProjectNode::read(&entry_path)?);
+                continue;
+            }
+
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let (name, class_name) = match script_kind(&file_name) {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let source = fs::read_to_string(&entry_path)?;
+
+            if name == "init" {
+                node.class_name = class_name;
+                node.source = Some(source);
+            } else {
+                node.children.push(ProjectNode {
+                    name: name.to_owned(),
+                    class_name,
+                    source: Some(source),
+                    children: Vec::new(),
+                });
+            }
+        }
+
+        Ok(node)
+    }
+
+    /// Adds this node and all of its descendants to the given tree.
+    pub fn insert_into(&self, tree: &mut RbxTree, parent_id: RbxId) {
+        let mut properties = HashMap::new();
+
+        if let Some(source) = &self.source {
+            properties.insert(
+                "Source".to_owned(),
+                RbxValue::String {
+                    value: source.clone(),
+                },
+            );
+        }
+
+        let id = tree.insert_instance(
+            RbxInstanceProperties {
+                name: self.name.clone(),
+                class_name: self.class_name.to_owned(),
+                properties,
+            },
+            parent_id,
+        );
+
+        for child in &self.children {
+            child.insert_into(tree, id);
+        }
+    }
+}
+
+/// Figures out the instance name and class of a script file based on its name,
+/// or returns `None` if the file isn't a script.
+fn script_kind(file_name: &str) -> Option<(&str, &'static str)> {
+    let stem = file_name
+        .strip_suffix(".lua")
+        .or_else(|| file_name.strip_suffix(".luau"))?;
+
+    if let Some(name) = stem.strip_suffix(".server") {
+        Some((name, "Script"))
+    } else if let Some(name) = stem.strip_suffix(".client") {
+        Some((name, "LocalScript"))
+    } else {
+        Some((stem, "ModuleScript"))
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    time::SystemTime,
+};
+
+use fs_err as fs;
+use sysinfo::{Process, System};
+
+use crate::KillSignal;
+
+/// A copy of Roblox Studio that we found installed.
+#[derive(Debug, Clone)]
+pub struct StudioInstall {
+    /// The path to the Studio executable.
+    pub executable: PathBuf,
+
+    /// When the executable was last modified, which is roughly when this
+    /// version was installed.
+    pub modified: SystemTime,
+
+    /// The name of the folder that the version was installed into, like
+    /// `version-0123456789abcdef`, if it was installed into one.
+    pub version: Option<String>,
+
+    /// The release channel that the version came from, like `live` or
+    /// `zcanary`, if we know it.
+    pub channel: Option<String>,
+}
+
+impl StudioInstall {
+    /// Whether this version of Studio came from the channel picked out by
+    /// `--studio-channel`.
+    pub fn matches_channel(&self, wanted: &str) -> bool {
+        self.channel.as_deref().map_or(false, |channel| {
+            normalize_channel(channel) == normalize_channel(wanted)
+        })
+    }
+}
+
+/// Channel names are compared ignoring case. The Roblox installer calls the
+/// live channel `LIVE`, or leaves its name empty.
+fn normalize_channel(channel: &str) -> String {
+    if channel.is_empty() {
+        "live".to_owned()
+    } else {
+        channel.to_lowercase()
+    }
+}
+
+/// Finds the versions of Roblox Studio that the Roblox installer has put in
+/// `%LOCALAPPDATA%\Roblox\Versions`, newest first. Each version gets its own
+/// folder there, and old versions aren't always cleaned up.
+pub fn windows_installs() -> Vec<StudioInstall> {
+    let mut installs = match env::var_os("LOCALAPPDATA") {
+        Some(local_app_data) => versions_in(
+            &PathBuf::from(local_app_data)
+                .join("Roblox")
+                .join("Versions"),
+        ),
+        None => Vec::new(),
+    };
+
+    // The installer records which channel it updates Studio from, but not
+    // which channel each version came from. The newest version is the one that
+    // it installed last, so that's the only one whose channel we know.
+    if let Some(newest) = installs.first_mut() {
+        newest.channel = installer_channel();
+    }
+
+    installs
+}
+
+/// The release channel that the Roblox installer updates Studio from, which it
+/// keeps in the registry. It only writes it down once Studio has been switched
+/// away from the live channel.
+#[cfg(windows)]
+fn installer_channel() -> Option<String> {
+    use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+
+    let channel = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey(r"Software\ROBLOX Corporation\Environments\RobloxStudio\Channel")
+        .and_then(|key| key.get_value::<String, _>("www.roblox.com"))
+        .unwrap_or_default();
+
+    Some(normalize_channel(&channel))
+}
+
+#[cfg(not(windows))]
+fn installer_channel() -> Option<String> {
+    None
+}
+
+/// Finds every copy of Roblox Studio that we know where to look for, newest
+/// first. On Linux, this looks inside of wine prefixes.
+pub fn all_installs(wine_prefix: Option<&Path>) -> Vec<StudioInstall> {
+    let mut installs = if cfg!(windows) {
+        windows_installs()
+    } else if cfg!(target_os = "macos") {
+        macos_install()
+            .and_then(|executable| {
+                let modified = fs::metadata(&executable).ok()?.modified().ok()?;
+
+                Some(StudioInstall {
+                    executable,
+                    modified,
+                    version: None,
+                    channel: None,
+                })
+            })
+            .into_iter()
+            .collect()
+    } else {
+        let mut installs: Vec<_> = wine_prefixes(wine_prefix)
+            .iter()
+            .filter_map(|prefix| fs::read_dir(prefix.join("drive_c/users")).ok())
+            .flatten()
+            .filter_map(|entry| Some(entry.ok()?.path().join("AppData/Local/Roblox/Versions")))
+            .flat_map(|versions_path| versions_in(&versions_path))
+            .collect();
+
+        // Vinegar keeps its copies of Studio outside of the wine prefix.
+        if let Some(home) = env::var_os("HOME") {
+            installs.extend(versions_in(
+                &PathBuf::from(home).join(".var/app/org.vinegarhq.Vinegar/data/vinegar/versions"),
+            ));
+        }
+
+        installs
+    };
+
+    installs.sort_by(|a, b| b.modified.cmp(&a.modified));
+    installs
+}
+
+/// Finds the copies of Studio in a folder with a subfolder for each version,
+/// newest first.
+fn versions_in(versions_path: &Path) -> Vec<StudioInstall> {
+    let entries = match fs::read_dir(versions_path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Could not read Studio versions folder: {}", err);
+            return Vec::new();
+        }
+    };
+
+    let mut installs: Vec<_> = entries
+        .filter_map(|entry| {
+            let version_path = entry.ok()?.path();
+            let executable = version_path.join("RobloxStudioBeta.exe");
+            let modified = fs::metadata(&executable).ok()?.modified().ok()?;
+
+            Some(StudioInstall {
+                executable,
+                modified,
+                version: version_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned()),
+                channel: None,
+            })
+        })
+        .collect();
+
+    installs.sort_by(|a, b| b.modified.cmp(&a.modified));
+    installs
+}
+
+/// Finds Roblox Studio's app bundle on macOS, checking the system-wide
+/// Applications folder before the user's own.
+pub fn macos_install() -> Option<PathBuf> {
+    let mut candidates = vec![PathBuf::from("/Applications/RobloxStudio.app")];
+
+    if let Some(home) = env::var_os("HOME") {
+        candidates.push(PathBuf::from(home).join("Applications/RobloxStudio.app"));
+    }
+
+    candidates
+        .into_iter()
+        .map(|app_path| macos_executable(&app_path))
+        .find(|executable| executable.is_file())
+}
+
+/// Turns a path to Studio's app bundle into a path to the executable inside
+/// of it, which is what we need to pass arguments to Studio. Paths that aren't
+/// to an app bundle are left alone.
+pub fn macos_executable(path: &Path) -> PathBuf {
+    if path
+        .extension()
+        .map_or(false, |extension| extension == "app")
+    {
+        path.join("Contents/MacOS/RobloxStudio")
+    } else {
+        path.to_owned()
+    }
+}
+
+/// Finds the folders that Roblox Studio might be writing its logs to.
+pub fn log_dirs(wine_prefix: Option<&Path>) -> Vec<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA")
+            .map(|local_app_data| PathBuf::from(local_app_data).join("Roblox").join("logs"))
+            .into_iter()
+            .collect()
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join("Library/Logs/Roblox"))
+            .into_iter()
+            .collect()
+    } else {
+        // Under wine, logs end up in the Windows user folder inside of the
+        // prefix. We don't know which user that is, so check all of them.
+        wine_prefixes(wine_prefix)
+            .iter()
+            .filter_map(|prefix| fs::read_dir(prefix.join("drive_c/users")).ok())
+            .flatten()
+            .filter_map(|entry| {
+                let logs_path = entry.ok()?.path().join("AppData/Local/Roblox/logs");
+                if logs_path.is_dir() {
+                    Some(logs_path)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// The wine prefixes that Studio might be installed in: the one we were given,
+/// or else Vinegar's and the default one.
+fn wine_prefixes(wine_prefix: Option<&Path>) -> Vec<PathBuf> {
+    match wine_prefix {
+        Some(wine_prefix) => vec![wine_prefix.to_owned()],
+        None => match env::var_os("HOME") {
+            Some(home) => {
+                let home = PathBuf::from(home);
+                vec![
+                    home.join(".var/app/org.vinegarhq.Vinegar/data/vinegar/prefixes/studio"),
+                    home.join(".wine"),
+                ]
+            }
+            None => Vec::new(),
+        },
+    }
+}
+
+/// What we need to know to find the copy of Studio that a run opened and close
+/// it.
+#[derive(Debug, Clone)]
+pub struct StudioSession {
+    pub server_id: String,
+    pub wine_prefix: Option<PathBuf>,
+    pub kill_signal: KillSignal,
+    pub executable_names: Vec<String>,
+}
+
+/// The names that Studio's executable goes by on this platform. Studio is
+/// usually `RobloxStudioBeta.exe` on Windows and under wine, but some builds
+/// and wrappers drop the "Beta", as Studio itself does on macOS.
+pub fn default_studio_executable_names() -> Vec<String> {
+    let names: &[&str] = if cfg!(target_os = "macos") {
+        &["RobloxStudio"]
+    } else {
+        &["RobloxStudioBeta.exe", "RobloxStudio.exe"]
+    };
+
+    names.iter().map(|name| (*name).to_owned()).collect()
+}
+
+/// Whether an executable is one of the given names, ignoring case and any
+/// `.exe` extension. The executable can be a full path, including a Windows
+/// path that wine was asked to run.
+fn is_studio_executable(executable: &str, executable_names: &[String]) -> bool {
+    let normalize = |name: &str| {
+        let name = name.to_lowercase();
+        match name.strip_suffix(".exe") {
+            Some(stem) => stem.to_owned(),
+            None => name,
+        }
+    };
+
+    let file_name = executable.rsplit(|c| c == '/' || c == '\\').next();
+
+    file_name.map_or(false, |file_name| {
+        let file_name = normalize(file_name);
+        executable_names
+            .iter()
+            .any(|name| normalize(name) == file_name)
+    })
+}
+
+/// Finds every running Roblox Studio process, leaving out any that are running
+/// in a different wine prefix than the one we were given.
+fn studio_processes<'a>(system: &'a System, studio_session: &StudioSession) -> Vec<&'a Process> {
+    let executable_names = &studio_session.executable_names;
+
+    if cfg!(target_os = "linux") {
+        // Studio runs under wine on Linux, so the process we're looking for is
+        // a wine process that was asked to run Studio.
+        system
+            .processes_by_name("wine".as_ref())
+            .filter(|process| {
+                process.cmd().first().map_or(false, |program| {
+                    is_studio_executable(&program.to_string_lossy(), executable_names)
+                })
+            })
+            .filter(|process| match &studio_session.wine_prefix {
+                Some(wine_prefix) => {
+                    let expected_var = format!("WINEPREFIX={}", wine_prefix.display());
+
+                    process
+                        .environ()
+                        .iter()
+                        .any(|var| var.to_string_lossy() == expected_var)
+                }
+                None => true,
+            })
+            .collect()
+    } else {
+        system
+            .processes()
+            .values()
+            .filter(|process| {
+                is_studio_executable(&process.name().to_string_lossy(), executable_names)
+            })
+            .collect()
+    }
+}
+
+/// Checks whether Roblox Studio is already running, which can cause the plugin
+/// to connect to the wrong copy of Studio.
+pub fn studio_is_running(studio_session: &StudioSession) -> bool {
+    let mut system = System::new_all();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+
+    !studio_processes(&system, studio_session).is_empty()
+}
+
+/// Finds the processes that have the session's temporary place open, which has
+/// the session's server ID in its name.
+fn session_processes<'a>(system: &'a System, studio_session: &StudioSession) -> Vec<&'a Process> {
+    let opened_our_place = |process: &Process| {
+        process
+            .cmd()
+            .iter()
+            .skip(1)
+            .any(|arg| arg.to_string_lossy().contains(&studio_session.server_id))
+    };
+
+    let mut ours: Vec<_> = studio_processes(&system, studio_session)
+        .into_iter()
+        .filter(|process| opened_our_place(process))
+        .collect();
+
+    // A Studio build or wrapper that we don't know the name of would never be
+    // found otherwise. The server ID is unique to this run, so any process
+    // that was given it is one that we started.
+    if ours.is_empty() {
+        let current_pid = sysinfo::get_current_pid().ok();
+
+        ours = system
+            .processes()
+            .values()
+            .filter(|process| Some(process.pid()) != current_pid && opened_our_place(process))
+            .collect();
+    }
+
+    ours
+}
+
+/// Checks whether the session's copy of Studio is still running. This is what
+/// to check instead of the process that we spawned, since the Studio launcher
+/// and wine both hand off to another process and exit.
+pub fn session_is_running(studio_session: &StudioSession) -> bool {
+    let mut system = System::new_all();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+
+    !session_processes(&system, studio_session).is_empty()
+}
+
+/// Closes any Roblox Studio instance that has the session's temporary place
+/// open.
+pub fn close_studio(studio_session: &StudioSession) {
+    let mut system = System::new_all();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+
+    for process in session_processes(&system, studio_session) {
+        log::debug!("Closing {}", process.name().to_string_lossy());
+
+        match studio_session.kill_signal {
+            KillSignal::Term => stop_gracefully(process),
+            KillSignal::Kill => {
+                process.kill();
+            }
+        }
+    }
+}
+
+/// Asks a Studio process to exit, falling back to killing it if it can't be
+/// asked.
+fn stop_gracefully(studio: &Process) {
+    // Windows doesn't have a notion of SIGTERM. Closing Studio's window with
+    // taskkill is the closest thing to it.
+    if cfg!(windows) {
+        let closed = process::Command::new("taskkill")
+            .arg("/PID")
+            .arg(studio.pid().to_string())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map_or(false, |status| status.success());
+
+        if !closed {
+            studio.kill();
+        }
+    } else if studio.kill_with(sysinfo::Signal::Term).is_none() {
+        studio.kill();
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    io::{self, LineWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use fs_err::File;
+use serde_json::json;
+
+use crate::{message_receiver::OutputLevel, OutputFormat};
+
+/// Writes every message received from Roblox Studio to a file as it arrives,
+/// so that there's a record of the run even if run-in-roblox is interrupted.
+pub struct Transcript {
+    output: LineWriter<File>,
+    format: OutputFormat,
+}
+
+impl Transcript {
+    /// Creates the transcript file, truncating it if it already exists.
+    pub fn create(path: &Path, format: OutputFormat) -> io::Result<Transcript> {
+        Ok(Transcript {
+            output: LineWriter::new(File::create(path)?),
+            format,
+        })
+    }
+
+    pub fn write_output(
+        &mut self,
+        level: OutputLevel,
+        body: &str,
+        source: Option<&str>,
+        line: Option<u32>,
+    ) -> io::Result<()> {
+        let timestamp = humantime::format_rfc3339_millis(SystemTime::now());
+
+        match self.format {
+            OutputFormat::Human | OutputFormat::Junit => {
+                write!(
+                    self.output,
+                    "{} [{}] ",
+                    timestamp,
+                    level.as_str().to_uppercase()
+                )?;
+
+                if let (Some(source), Some(line)) = (source, line) {
+                    write!(self.output, "[{}:{}] ", source, line)?;
+                }
+
+                writeln!(self.output, "{}", body)
+            }
+            OutputFormat::Json => writeln!(
+                self.output,
+                "{}",
+                json!({
+                    "timestamp": timestamp.to_string(),
+                    "level": level.as_str(),
+                    "body": body,
+                    "source": source,
+                    "line": line,
+                })
+            ),
+        }
+    }
+}
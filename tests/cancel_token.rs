@@ -0,0 +1,26 @@
+use std::thread;
+
+use run_in_roblox::{CancelToken, PlaceRunner};
+
+#[test]
+fn clones_share_cancellation() {
+    let token = CancelToken::new();
+    let other = token.clone();
+    assert!(!other.is_cancelled());
+
+    thread::spawn(move || token.cancel()).join().unwrap();
+    assert!(other.is_cancelled());
+}
+
+#[test]
+fn runner_uses_the_token_it_was_built_with() {
+    let token = CancelToken::new();
+    let runner = PlaceRunner::builder()
+        .place_path("place.rbxlx")
+        .cancel_token(token.clone())
+        .build()
+        .unwrap();
+
+    token.cancel();
+    assert!(runner.cancel_token().is_cancelled());
+}
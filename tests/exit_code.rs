@@ -0,0 +1,25 @@
+use run_in_roblox::ExitCode;
+
+#[test]
+fn exit_codes_are_stable() {
+    assert_eq!(ExitCode::Success.code(), 0);
+    assert_eq!(ExitCode::ScriptError.code(), 1);
+    assert_eq!(ExitCode::HostError.code(), 2);
+    assert_eq!(ExitCode::Timeout.code(), 3);
+    assert_eq!(ExitCode::StudioCrash.code(), 4);
+    assert_eq!(ExitCode::Aborted.code(), 5);
+    assert_eq!(ExitCode::Interrupted.code(), 130);
+}
+
+#[test]
+fn help_lists_every_exit_code() {
+    let help = ExitCode::help();
+
+    for exit_code in &ExitCode::ALL {
+        assert!(
+            help.contains(exit_code.description()),
+            "help is missing {:?}",
+            exit_code
+        );
+    }
+}
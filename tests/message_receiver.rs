@@ -0,0 +1,283 @@
+//! Exercises the HTTP protocol between the plugin and run-in-roblox, with ureq
+//! standing in for the plugin.
+
+use std::{io::Write, net::Ipv4Addr, time::Duration};
+
+use flate2::{write::GzEncoder, Compression};
+
+use run_in_roblox::{
+    Message, MessageReceiver, MessageReceiverOptions, OutputLevel, RobloxMessage, PROTOCOL_VERSION,
+    SERVER_ID_HEADER,
+};
+
+const SERVER_ID: &str = "run-in-roblox-test";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn start_receiver() -> (MessageReceiver, String) {
+    let receiver = MessageReceiver::start(MessageReceiverOptions {
+        bind_address: Ipv4Addr::LOCALHOST.into(),
+        port: 0,
+        server_id: SERVER_ID.to_owned(),
+        scripts: vec!["print('hello')".to_owned()],
+        channel_capacity: 16,
+    })
+    .expect("Could not start message receiver");
+
+    let url = format!("http://127.0.0.1:{}", receiver.port());
+    (receiver, url)
+}
+
+fn post(url: &str, server_id: &str, body: &str) -> Result<ureq::Response, ureq::Error> {
+    ureq::post(url)
+        .set(SERVER_ID_HEADER, server_id)
+        .set("Content-Type", "application/json")
+        .send_string(body)
+}
+
+#[test]
+fn ping_returns_server_id() {
+    let (receiver, url) = start_receiver();
+
+    let body = ureq::get(&url)
+        .set(SERVER_ID_HEADER, SERVER_ID)
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert_eq!(body, SERVER_ID);
+
+    receiver.stop();
+}
+
+#[test]
+fn ping_without_server_id_is_rejected() {
+    let (receiver, url) = start_receiver();
+
+    match ureq::get(&url).call() {
+        Err(ureq::Error::Status(status, _)) => assert_eq!(status, 403),
+        other => panic!("Expected a 403, got {:?}", other),
+    }
+
+    receiver.stop();
+}
+
+#[test]
+fn start_and_stop() {
+    let (receiver, url) = start_receiver();
+
+    let start_body = format!(r#"{{"protocol_version": {}}}"#, PROTOCOL_VERSION);
+    let host_version = post(&format!("{}/start", url), SERVER_ID, &start_body)
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert_eq!(host_version, PROTOCOL_VERSION.to_string());
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Some(Message::Start)
+    ));
+
+    post(&format!("{}/stop", url), SERVER_ID, "").unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Some(Message::Stop)
+    ));
+
+    receiver.stop();
+}
+
+#[test]
+fn mismatched_protocol_version_is_reported() {
+    let (receiver, url) = start_receiver();
+
+    let start_body = format!(r#"{{"protocol_version": {}}}"#, PROTOCOL_VERSION + 1);
+    post(&format!("{}/start", url), SERVER_ID, &start_body).unwrap();
+
+    match receiver.recv_timeout(TIMEOUT) {
+        Some(Message::ProtocolMismatch { plugin_version }) => {
+            assert_eq!(plugin_version, Some(PROTOCOL_VERSION + 1));
+        }
+        other => panic!("Expected a protocol mismatch, got {:?}", other),
+    }
+
+    receiver.stop();
+}
+
+#[test]
+fn messages_are_forwarded() {
+    let (receiver, url) = start_receiver();
+
+    let body = r#"[
+        {"type": "Output", "level": "Warning", "body": "careful", "source": "Script", "line": 3, "context": "server"},
+        {"type": "Exit", "code": 7}
+    ]"#;
+    post(&format!("{}/messages", url), SERVER_ID, body).unwrap();
+
+    let messages = match receiver.recv_timeout(TIMEOUT) {
+        Some(Message::Messages(messages)) => messages,
+        other => panic!("Expected messages, got {:?}", other),
+    };
+
+    assert_eq!(messages.len(), 2);
+
+    match &messages[0] {
+        RobloxMessage::Output {
+            level,
+            body,
+            source,
+            line,
+            studio_time,
+            context,
+        } => {
+            assert_eq!(*level, OutputLevel::Warning);
+            assert_eq!(body, "careful");
+            assert_eq!(source.as_deref(), Some("Script"));
+            assert_eq!(*line, Some(3));
+            assert_eq!(*studio_time, None);
+            assert_eq!(context.as_deref(), Some("server"));
+        }
+        other => panic!("Expected output, got {:?}", other),
+    }
+
+    assert!(matches!(messages[1], RobloxMessage::Exit { code: 7 }));
+
+    receiver.stop();
+}
+
+#[test]
+fn output_is_classified_by_message_type() {
+    let (receiver, url) = start_receiver();
+
+    let body = r#"[
+        {"type": "Output", "level": 0, "body": "print"},
+        {"type": "Output", "level": 1, "body": "info"},
+        {"type": "Output", "level": 2, "body": "warn"},
+        {"type": "Output", "level": 3, "body": "error"}
+    ]"#;
+    post(&format!("{}/messages", url), SERVER_ID, body).unwrap();
+
+    let messages = match receiver.recv_timeout(TIMEOUT) {
+        Some(Message::Messages(messages)) => messages,
+        other => panic!("Expected messages, got {:?}", other),
+    };
+
+    let levels: Vec<_> = messages
+        .iter()
+        .map(|message| match message {
+            RobloxMessage::Output { level, .. } => *level,
+            other => panic!("Expected output, got {:?}", other),
+        })
+        .collect();
+
+    assert_eq!(
+        levels,
+        [
+            OutputLevel::Print,
+            OutputLevel::Info,
+            OutputLevel::Warning,
+            OutputLevel::Error,
+        ]
+    );
+
+    receiver.stop();
+}
+
+#[test]
+fn gzipped_messages_are_decompressed() {
+    let (receiver, url) = start_receiver();
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(br#"[{"type": "Exit", "code": 3}]"#)
+        .unwrap();
+    let body = encoder.finish().unwrap();
+
+    ureq::post(&format!("{}/messages", url))
+        .set(SERVER_ID_HEADER, SERVER_ID)
+        .set("Content-Type", "application/json")
+        .set("Content-Encoding", "gzip")
+        .send_bytes(&body)
+        .unwrap();
+
+    match receiver.recv_timeout(TIMEOUT) {
+        Some(Message::Messages(messages)) => {
+            assert!(matches!(messages[..], [RobloxMessage::Exit { code: 3 }]));
+        }
+        other => panic!("Expected messages, got {:?}", other),
+    }
+
+    receiver.stop();
+}
+
+#[test]
+fn invalid_messages_are_rejected() {
+    let (receiver, url) = start_receiver();
+
+    match post(&format!("{}/messages", url), SERVER_ID, "not json") {
+        Err(ureq::Error::Status(status, _)) => assert_eq!(status, 400),
+        other => panic!("Expected a 400, got {:?}", other),
+    }
+
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_none());
+
+    receiver.stop();
+}
+
+#[test]
+fn unreadable_messages_are_skipped() {
+    let (receiver, url) = start_receiver();
+
+    let body = r#"[
+        {"type": "Progress", "current": 0.5, "total": 1},
+        {"type": "Exit", "code": 1}
+    ]"#;
+    post(&format!("{}/messages", url), SERVER_ID, body).unwrap();
+
+    let messages = match receiver.recv_timeout(TIMEOUT) {
+        Some(Message::Messages(messages)) => messages,
+        other => panic!("Expected messages, got {:?}", other),
+    };
+
+    assert_eq!(messages.len(), 1);
+    assert!(matches!(messages[0], RobloxMessage::Exit { code: 1 }));
+
+    receiver.stop();
+}
+
+#[test]
+fn scripts_are_served() {
+    let (receiver, url) = start_receiver();
+
+    let script = ureq::get(&format!("{}/scripts/1", url))
+        .set(SERVER_ID_HEADER, SERVER_ID)
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert!(script.contains("print('hello')"));
+
+    match ureq::get(&format!("{}/scripts/2", url))
+        .set(SERVER_ID_HEADER, SERVER_ID)
+        .call()
+    {
+        Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+        other => panic!("Expected a 404, got {:?}", other),
+    }
+
+    receiver.stop();
+}
+
+#[test]
+fn wrong_server_id_is_rejected() {
+    let (receiver, url) = start_receiver();
+
+    let body = r#"[{"type": "Output", "level": "Print", "body": "fake"}]"#;
+
+    match post(&format!("{}/messages", url), "someone-else", body) {
+        Err(ureq::Error::Status(status, _)) => assert_eq!(status, 403),
+        other => panic!("Expected a 403, got {:?}", other),
+    }
+
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_none());
+
+    receiver.stop();
+}
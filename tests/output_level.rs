@@ -0,0 +1,92 @@
+use colored::Color;
+use run_in_roblox::{OutputCounts, OutputLevel};
+
+#[test]
+fn each_level_has_its_own_count() {
+    let levels = [
+        OutputLevel::Print,
+        OutputLevel::Info,
+        OutputLevel::Warning,
+        OutputLevel::Error,
+    ];
+
+    for &level in &levels {
+        let mut counts = OutputCounts::default();
+        counts.count(level);
+
+        let expected = OutputCounts {
+            errors: (level == OutputLevel::Error) as u32,
+            warnings: (level == OutputLevel::Warning) as u32,
+            infos: (level == OutputLevel::Info) as u32,
+            prints: (level == OutputLevel::Print) as u32,
+        };
+
+        assert_eq!(counts, expected, "counting {:?}", level);
+    }
+}
+
+#[test]
+fn counts_since_earlier_counts() {
+    let mut counts = OutputCounts::default();
+    counts.count(OutputLevel::Print);
+    counts.count(OutputLevel::Error);
+
+    let earlier = counts;
+    counts.count(OutputLevel::Print);
+    counts.count(OutputLevel::Warning);
+
+    assert_eq!(
+        counts.since(&earlier),
+        OutputCounts {
+            errors: 0,
+            warnings: 1,
+            infos: 0,
+            prints: 1,
+        }
+    );
+}
+
+#[test]
+fn level_colors() {
+    assert_eq!(OutputLevel::Print.color(), None);
+    assert_eq!(OutputLevel::Info.color(), Some(Color::Cyan));
+    assert_eq!(OutputLevel::Warning.color(), Some(Color::Yellow));
+    assert_eq!(OutputLevel::Error.color(), Some(Color::Red));
+}
+
+#[test]
+fn levels_round_trip_through_strings() {
+    let levels = [
+        OutputLevel::Print,
+        OutputLevel::Info,
+        OutputLevel::Warning,
+        OutputLevel::Error,
+    ];
+
+    for &level in &levels {
+        assert_eq!(level.as_str().parse::<OutputLevel>(), Ok(level));
+    }
+}
+
+#[test]
+fn log_service_message_types() {
+    // The values of Enum.MessageType, which the plugin sends as-is.
+    assert_eq!(OutputLevel::from_message_type(0), OutputLevel::Print);
+    assert_eq!(OutputLevel::from_message_type(1), OutputLevel::Info);
+    assert_eq!(OutputLevel::from_message_type(2), OutputLevel::Warning);
+    assert_eq!(OutputLevel::from_message_type(3), OutputLevel::Error);
+    assert_eq!(OutputLevel::from_message_type(42), OutputLevel::Info);
+}
+
+#[test]
+fn levels_deserialize_from_message_types_and_names() {
+    let from_json = |json: &str| serde_json::from_str::<OutputLevel>(json).unwrap();
+
+    assert_eq!(from_json("0"), OutputLevel::Print);
+    assert_eq!(from_json("2"), OutputLevel::Warning);
+    assert_eq!(from_json("3"), OutputLevel::Error);
+    assert_eq!(from_json(r#""Warning""#), OutputLevel::Warning);
+    assert_eq!(from_json(r#""Info""#), OutputLevel::Info);
+
+    assert!(serde_json::from_str::<OutputLevel>(r#""Loud""#).is_err());
+}
@@ -0,0 +1,35 @@
+use run_in_roblox::parse_env_var;
+
+#[test]
+fn key_and_value_are_split() {
+    assert_eq!(
+        parse_env_var("RBX_MODE=test"),
+        Ok(("RBX_MODE".to_owned(), "test".to_owned()))
+    );
+}
+
+#[test]
+fn only_the_first_equals_sign_splits() {
+    assert_eq!(
+        parse_env_var("FLAGS=a=1,b=2"),
+        Ok(("FLAGS".to_owned(), "a=1,b=2".to_owned()))
+    );
+}
+
+#[test]
+fn values_can_be_empty() {
+    assert_eq!(
+        parse_env_var("EMPTY="),
+        Ok(("EMPTY".to_owned(), String::new()))
+    );
+}
+
+#[test]
+fn missing_equals_sign_is_rejected() {
+    assert!(parse_env_var("KEY").is_err());
+}
+
+#[test]
+fn missing_name_is_rejected() {
+    assert!(parse_env_var("=value").is_err());
+}
@@ -0,0 +1,11 @@
+use run_in_roblox::wrap_script;
+
+#[test]
+fn wrapped_scripts_keep_their_line_numbers() {
+    let wrapped = wrap_script("print('first')\nerror('second')");
+
+    let lines: Vec<_> = wrapped.lines().collect();
+    assert!(lines[0].ends_with("print('first')"));
+    assert_eq!(lines[1], "error('second')");
+    assert_eq!(lines[2], "end");
+}
@@ -0,0 +1,23 @@
+use run_in_roblox::validate_port;
+
+#[test]
+fn port_zero_is_rejected() {
+    assert!(validate_port(0).is_err());
+}
+
+#[test]
+fn unprivileged_ports_are_accepted() {
+    assert!(validate_port(1024).is_ok());
+    assert!(validate_port(50312).is_ok());
+    assert!(validate_port(u16::MAX).is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn privileged_ports_are_only_accepted_for_root() {
+    // SAFETY: geteuid takes no arguments and can't fail.
+    let is_root = unsafe { libc::geteuid() } == 0;
+
+    assert_eq!(validate_port(80).is_ok(), is_root);
+    assert_eq!(validate_port(1023).is_ok(), is_root);
+}
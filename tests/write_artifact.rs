@@ -0,0 +1,48 @@
+use std::fs;
+
+use run_in_roblox::write_artifact;
+
+#[test]
+fn files_are_written_inside_the_artifact_folder() {
+    let artifact_dir = tempfile::tempdir().unwrap();
+
+    write_artifact(artifact_dir.path(), "reports/./result.txt", "passed").unwrap();
+
+    let written = fs::read_to_string(artifact_dir.path().join("reports/result.txt")).unwrap();
+    assert_eq!(written, "passed");
+}
+
+#[test]
+fn parent_folders_are_rejected() {
+    let parent = tempfile::tempdir().unwrap();
+    let artifact_dir = parent.path().join("artifacts");
+
+    for relative_path in &["../escape", "nested/../../escape", ".."] {
+        assert!(
+            write_artifact(&artifact_dir, relative_path, "escaped").is_err(),
+            "{} was allowed",
+            relative_path
+        );
+    }
+
+    assert!(!parent.path().join("escape").exists());
+}
+
+#[test]
+fn absolute_paths_are_rejected() {
+    let artifact_dir = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    let target = outside.path().join("escape");
+
+    let result = write_artifact(artifact_dir.path(), target.to_str().unwrap(), "escaped");
+
+    assert!(result.is_err());
+    assert!(!target.exists());
+}
+
+#[test]
+fn empty_paths_are_rejected() {
+    let artifact_dir = tempfile::tempdir().unwrap();
+
+    assert!(write_artifact(artifact_dir.path(), "", "nothing").is_err());
+}